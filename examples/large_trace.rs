@@ -0,0 +1,26 @@
+use std::env;
+use std::io::{ self, BufWriter, Write };
+
+// Writes a trace of mixed scheduler events to stdout, to time the parser on a large file, e.g. with the fast-parse feature
+// The argument is the number of rounds of events, about 1KB each
+fn main() -> io::Result<()> {
+    let rounds: u64 = env::args().nth(1).and_then(|rounds| rounds.parse().ok()).unwrap_or(200_000);
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "cpus=8")?;
+    for round in 0..rounds {
+        let cpu = round % 8;
+        let next = (cpu + 1) % 8;
+        let pid = 1000 + round % 500;
+        let time = |step: u64| 100.0 + (round * 10 + step) as f64 / 1_000_000.0;
+        writeln!(out, "            bash-{pid}  [{cpu:03}]  {:.6}: sched_waking:         comm=Web Content pid={} prio=120 target_cpu={next:03}", time(0), pid + 1)?;
+        writeln!(out, "            bash-{pid}  [{cpu:03}]  {:.6}: sched_migrate_task:   comm=Web Content pid={} prio=120 orig_cpu={cpu} dest_cpu={next}", time(1), pid + 1)?;
+        writeln!(out, "          <idle>-0     [{next:03}]  {:.6}: sched_wakeup:         Web Content:{} [120] CPU:{next:03}", time(2), pid + 1)?;
+        writeln!(out, "          <idle>-0     [{next:03}]  {:.6}: sched_switch:         swapper/{next}:0 [120] R ==> Web Content:{} [120]", time(3), pid + 1)?;
+        writeln!(out, "            bash-{pid}  [{cpu:03}]  {:.6}: sched_switch:         prev_comm=bash prev_pid={pid} prev_prio=120 prev_state=S ==> next_comm=kworker/{cpu}:1 next_pid=77 next_prio=120", time(4))?;
+        writeln!(out, "     kworker/{cpu}:1-77    [{cpu:03}]  {:.6}: sched_process_fork:   comm=kworker/{cpu}:1 pid=77 child_comm=kworker/{cpu}:1 child_pid={}", time(5), pid + 500)?;
+        writeln!(out, "     kworker/{cpu}:1-77    [{cpu:03}]  {:.6}: sched_swap_numa:      src_pid={pid} src_tgid={pid} src_ngid=0 src_cpu={cpu} src_nid=0 dst_pid=77 dst_tgid=77 dst_ngid=0 dst_cpu={next} dst_nid=1", time(6))?;
+        writeln!(out, "     kworker/{cpu}:1-77    [{cpu:03}]  {:.6}: irq_handler_entry:    irq=24 name=eth0", time(7))?;
+        writeln!(out, "     kworker/{cpu}:1-77    [{cpu:03}]  {:.6}: sched_switch:         kworker/{cpu}:1:77 [120] I ==> swapper/{cpu}:0 [120]", time(8))?;
+    }
+    out.flush()
+}
@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+// time of every stage of the file being plotted, None when not benchmarking
+static STAGES: Mutex<Option<Vec<(&'static str, Duration)>>> = Mutex::new(None);
+
+// start recording the stages, from here on every plotted file reports its own
+pub fn start() {
+    *STAGES.lock().unwrap() = Some(Vec::new());
+}
+
+// add the time of a stage, a stage reached more than once adds up
+fn add(stages: &mut Vec<(&'static str, Duration)>, stage: &'static str, elapsed: Duration) {
+    match stages.iter_mut().find(|(name, _)| *name == stage) {
+        Some((_, total)) => *total += elapsed,
+        None => stages.push((stage, elapsed)),
+    }
+}
+
+// add the time of a stage of the file being plotted, if benchmarking
+pub fn record(stage: &'static str, elapsed: Duration) {
+    if let Some(stages) = STAGES.lock().unwrap().as_mut() {
+        add(stages, stage, elapsed);
+    }
+}
+
+// stages recorded since the last call, in the order they were first reached
+pub fn take() -> Vec<(&'static str, Duration)> {
+    STAGES.lock().unwrap().as_mut().map(std::mem::take).unwrap_or_default()
+}
+
+// Table of the stages of a file with their share of the total, which also covers the time between the stages
+// The time left once the parse, color table and write are taken out is the building of the plot itself
+pub fn print_table(filename: &str, mut stages: Vec<(&'static str, Duration)>, total: Duration) {
+    let timed: Duration = stages.iter().map(|(_, elapsed)| *elapsed).sum();
+    let write = stages.iter().position(|(name, _)| *name == "plot write").unwrap_or(stages.len());
+    stages.insert(write, ("plot build", total.saturating_sub(timed)));
+
+    println!("benchmark of {}:", filename);
+    println!("  {:<18} {:>12} {:>7}", "stage", "time", "share");
+    for (name, elapsed) in &stages {
+        println!("  {:<18} {:>12.3?} {:>6.1}%", name, elapsed, 100.0 * elapsed.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE));
+    }
+    println!("  {:<18} {:>12.3?}", "total", total);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_stages_add_up() {
+        // a local list, the plots of other tests record in the global one
+        let mut stages = Vec::new();
+        add(&mut stages, "header parse", Duration::from_millis(2));
+        add(&mut stages, "event parse", Duration::from_millis(10));
+        add(&mut stages, "header parse", Duration::from_millis(3));
+        assert_eq!(stages, vec![("header parse", Duration::from_millis(5)), ("event parse", Duration::from_millis(10))]);
+    }
+}
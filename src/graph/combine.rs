@@ -0,0 +1,232 @@
+use std::collections::{ HashMap, HashSet };
+use std::fs::File;
+use std::io::{ BufRead, BufReader, BufWriter, Write };
+use std::iter::Peekable;
+use serde_json::Value;
+use crate::graph::parser::{ line_cpu_and_timestamp, TraceParser };
+use crate::read_config::Config;
+use plotly::common::{ Anchor, Title };
+use plotly::layout::{ Annotation, Axis, Layout };
+use plotly::Plot;
+use super::{ add_annotations, data_graph, draw_file, duration_range, get_cpu_axis, node_separators, utilization_annotations, write_plot, JsonTrace };
+
+// plotly only has layout setters for 8 y axes
+const MAX_SUBPLOTS: usize = 8;
+
+// plotly names the first axis "y", then "y2", "y3"...
+fn axis_id(prefix: &str, subplot: usize) -> String {
+    if subplot == 1 {
+        String::from(prefix)
+    } else {
+        format!("{}{}", prefix, subplot)
+    }
+}
+
+fn set_y_axis(layout: Layout, subplot: usize, axis: Axis) -> Layout {
+    match subplot {
+        1 => layout.y_axis(axis),
+        2 => layout.y_axis2(axis),
+        3 => layout.y_axis3(axis),
+        4 => layout.y_axis4(axis),
+        5 => layout.y_axis5(axis),
+        6 => layout.y_axis6(axis),
+        7 => layout.y_axis7(axis),
+        8 => layout.y_axis8(axis),
+        _ => panic!("At most {} files can be combined", MAX_SUBPLOTS),
+    }
+}
+
+// All files stacked top to bottom in a single plot named name, sharing the duration axis
+// Each file keeps its own cpu axis, with the file name above its subplot
+pub fn combined_graph(filepaths: &[String], config: &Config, name: &str, title: &str) {
+    let options = &config.graph;
+    if filepaths.len() > MAX_SUBPLOTS {
+        panic!("At most {} files can be combined, got {}", MAX_SUBPLOTS, filepaths.len());
+    }
+
+    let mut plot = Plot::new();
+    let mut layout = Layout::new().auto_size(true);
+    let mut start = f64::MAX;
+    let mut end = f64::MIN;
+    let mut legends = HashSet::new();
+    let count = filepaths.len() as f64;
+    if options.runqueue_depth || options.show_switch_rate || options.activity_strip {
+        eprintln!("Warning: the runqueue depth, switch rate and activity strip overlays are not drawn in combine mode");
+    }
+    if options.trace_start_wallclock.is_some() {
+        eprintln!("Warning: trace_start_wallclock is ignored in combine mode, the files do not share a start time");
+    }
+
+    for (index, filepath) in filepaths.iter().enumerate() {
+        let subplot = index + 1;
+        let filename = filepath.split("/").last().unwrap();
+
+        let mut file_plot = Plot::new();
+        let Some((reader, y_axis, utilization)) = draw_file(filepath, config, &mut file_plot) else {
+            eprintln!("Warning: no events to plot in {}", filename);
+            continue;
+        };
+        let range = duration_range(options, &reader).0;
+        start = start.min(range[0]);
+        end = end.max(range[1]);
+
+        for trace in file_plot.data().iter() {
+            let mut json: Value = serde_json::from_str(&trace.to_json()).expect("Failed to read trace");
+            // the secondary axes of the overlays are taken by the next subplots
+            if json["yaxis"].as_str().is_some_and(|axis| axis != "y") {
+                continue;
+            }
+            json["xaxis"] = Value::from("x");
+            json["yaxis"] = Value::from(axis_id("y", subplot));
+
+            // the same legend entry is only shown once, for the first file that has it
+            if json["showlegend"] != Value::Bool(false) {
+                let name = json["name"].as_str().unwrap_or_default().to_string();
+                if !legends.insert(name) {
+                    json["showlegend"] = Value::Bool(false);
+                }
+            }
+            plot.add_trace(Box::new(JsonTrace(json)));
+        }
+
+        // leave some space between subplots for the file name
+        let top = 1.0 - index as f64 / count;
+        let bottom = 1.0 - subplot as f64 / count;
+        let domain = [bottom + 0.02, top - 0.06];
+        layout = set_y_axis(layout, subplot, get_cpu_axis(config, &reader, &y_axis)
+                                                .domain(&domain)
+                                                .anchor(String::from("x")));
+        layout.add_annotation(Annotation::new()
+                                .text(filename)
+                                .x_ref("paper")
+                                .y_ref("paper")
+                                .x(0.5)
+                                .y(domain[1])
+                                .y_anchor(Anchor::Bottom)
+                                .show_arrow(false));
+        if options.show_utilization {
+            for annotation in utilization_annotations(&utilization, &y_axis, &axis_id("y", subplot)) {
+                layout.add_annotation(annotation);
+            }
+        }
+        if options.node_separators && options.view == "cpu" {
+            let (shapes, annotations) = node_separators(&y_axis, &config.machine, &axis_id("y", subplot));
+            shapes.into_iter().for_each(|shape| layout.add_shape(shape));
+            annotations.into_iter().for_each(|annotation| layout.add_annotation(annotation));
+        }
+    }
+
+    // covers the longest file, or all of them with absolute timestamps
+    add_annotations(&mut layout, options, &[start, end], None);
+    let x_axis_title = format!("Duration: {:.*} seconds", options.timestamp_precision, end - start);
+    layout = layout.x_axis(
+                        Axis::new()
+                        .title(Title::new(&x_axis_title))
+                        .range(vec![start, end])
+                        .anchor(axis_id("y", filepaths.len()))
+                        .show_grid(options.show_grid));
+
+    if options.line_marker_count > 0 && options.line_marker_count <= 25 {
+        layout = layout.hover_distance(100);
+    }
+
+    if options.show_title {
+        layout = layout.title(Title::new(title));
+    }
+
+    plot.set_layout(layout);
+    write_plot(&mut plot, options, name);
+}
+
+fn is_session_header(line: &str) -> bool {
+    line.trim().starts_with("cpus=")
+}
+
+// One subplot per session of a trace made of concatenated reports, each starting at its cpus= header
+// The sessions are counted before anything is written, then written to a temporary directory,
+// removed when dropped even if the plot panics
+pub fn sessions_graph(filepath: &str, config: &Config) {
+    let filename = filepath.split("/").last().unwrap();
+    let count = BufReader::new(File::open(filepath).expect("Failed to open file")).lines()
+                    .filter(|line| line.as_ref().is_ok_and(|line| is_session_header(line)))
+                    .count();
+    if count > MAX_SUBPLOTS {
+        panic!("At most {} sessions can be plotted, {} has {}", MAX_SUBPLOTS, filename, count);
+    }
+
+    let dir = tempfile::Builder::new().prefix("tracing-tool-sessions").tempdir().expect("Failed to create a temporary directory");
+    let reader = BufReader::new(File::open(filepath).expect("Failed to open file"));
+    let mut sessions: Vec<String> = Vec::new();
+    let mut writer: Option<BufWriter<File>> = None;
+    for line in reader.lines() {
+        let line = line.expect("Error while reading trace");
+        if is_session_header(&line) {
+            // named after the trace, as the name is shown above the subplot
+            let session = dir.path().join(format!("{}-session{}", filename, sessions.len() + 1)).to_string_lossy().into_owned();
+            writer = Some(BufWriter::new(File::create(&session).expect("Failed to create session")));
+            sessions.push(session);
+        }
+        if let Some(writer) = writer.as_mut() {
+            writeln!(writer, "{}", line).expect("Error while writing session");
+        }
+    }
+    drop(writer);
+
+    combined_graph(&sessions, config, filename, &format!("Sessions: {}", filename));
+}
+
+// event lines of a trace with their timestamp, the other lines are skipped
+fn event_lines(filepath: &str) -> Peekable<impl Iterator<Item = (f64, String)>> {
+    BufReader::new(File::open(filepath).expect("Failed to open file")).lines()
+        .map(|line| line.expect("Error while reading trace"))
+        .filter_map(|line| line_cpu_and_timestamp(&line).map(|(_, timestamp)| (timestamp, line)))
+        .peekable()
+}
+
+// One timeline of traces recorded on separate groups of cpus of the same machine, as if they were a single trace
+// The events are interleaved by timestamp without any offset, so the traces must share their clock
+// A cpu with events in more than one file cannot be told apart, and the merge is refused
+pub fn merged_graph(filepaths: &[String], config: &Config) {
+    let mut owners: HashMap<u32, usize> = HashMap::new();
+    let mut cpu_count = 0;
+    for (index, filepath) in filepaths.iter().enumerate() {
+        cpu_count = cpu_count.max(TraceParser::new(filepath).cpu_count);
+        let reader = BufReader::new(File::open(filepath).expect("Failed to open file"));
+        for line in reader.lines() {
+            let Some((cpu, _)) = line_cpu_and_timestamp(&line.expect("Error while reading trace")) else {
+                continue;
+            };
+            cpu_count = cpu_count.max(cpu + 1);
+            let owner = *owners.entry(cpu).or_insert(index);
+            if owner != index {
+                panic!("cpu {} has events in both {} and {}, merge_files needs traces of separate cpus", cpu, filepaths[owner], filepath);
+            }
+        }
+    }
+
+    // the files are already in timestamp order, so the next event is the earliest of the next line of every file
+    // the merged trace is written to a temporary directory, removed when dropped even if the plot panics,
+    // and named after the files merged, which names the plot
+    let dir = tempfile::Builder::new().prefix("tracing-tool-merge").tempdir().expect("Failed to create a temporary directory");
+    let name = filepaths.iter().map(|filepath| filepath.split("/").last().unwrap()).collect::<Vec<&str>>().join("+");
+    let merged = dir.path().join(name).to_string_lossy().into_owned();
+    let mut writer = BufWriter::new(File::create(&merged).expect("Failed to create merged trace"));
+    writeln!(writer, "cpus={}", cpu_count).expect("Error while writing merged trace");
+    let mut files: Vec<_> = filepaths.iter().map(|filepath| event_lines(filepath)).collect();
+    loop {
+        let next = files.iter_mut().enumerate()
+                    .filter_map(|(index, lines)| lines.peek().map(|(timestamp, _)| (index, *timestamp)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((index, _)) = next else {
+            break;
+        };
+        let (_, line) = files[index].next().unwrap();
+        writeln!(writer, "{}", line).expect("Error while writing merged trace");
+    }
+    drop(writer);
+
+    if !config.graph.quiet {
+        println!("merged {} files on {} cpus", filepaths.len(), owners.len());
+    }
+    data_graph(&merged, config);
+}
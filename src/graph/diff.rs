@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use crate::graph::parser::*;
+use crate::read_config::{ Config, Machine };
+use plotly::common::{ HoverInfo, Line, Mode, Title };
+use plotly::color::NamedColor;
+use plotly::layout::{ Axis, Layout };
+use plotly::{ Plot, Scatter };
+use super::{ get_cpu_axis, get_sched_switch_events, get_socket_axis, get_socket_order, get_y_axis, hover_origin, time_origin, write_plot };
+
+// placement of a candidate run compared to the baseline, in the order of the legend
+const CATEGORIES: [(&str, NamedColor); 5] = [
+    ("same cpu", NamedColor::LightGray),
+    ("other cpu, same socket", NamedColor::Orange),
+    ("other socket", NamedColor::Red),
+    ("not running in baseline", NamedColor::MediumPurple),
+    ("new task", NamedColor::SteelBlue),
+];
+
+// x, y and hover text of the runs of a category, a run is a line from its start to its end followed by a gap
+type Segments = (Vec<Option<f64>>, Vec<Option<u32>>, Vec<String>);
+
+// a task running on a cpu from start to end, in seconds from the first event of its trace
+struct Run {
+    command: String,
+    pid: u32,
+    cpu: u32,
+    start: f64,
+    end: f64,
+}
+
+// baseline runs of a task sorted by start, with the latest end of the runs up to each one
+// so the runs overlapping a window are found without going over all of them
+struct Timeline<'a> {
+    runs: Vec<&'a Run>,
+    max_end: Vec<f64>,
+}
+
+impl<'a> Timeline<'a> {
+    fn new(mut runs: Vec<&'a Run>) -> Self {
+        runs.sort_by(|a, b| a.start.total_cmp(&b.start));
+        let max_end = runs.iter().scan(f64::MIN, |max_end, run| {
+            *max_end = run.end.max(*max_end);
+            Some(*max_end)
+        }).collect();
+        Timeline { runs, max_end }
+    }
+
+    // cpus the task ran on between start and end, sorted
+    fn cpus(&self, start: f64, end: f64) -> Vec<u32> {
+        let first = self.max_end.partition_point(|max_end| *max_end <= start);
+        let mut cpus: Vec<u32> = self.runs[first..].iter()
+                                    .take_while(|run| run.start < end)
+                                    .filter(|run| run.end > start)
+                                    .map(|run| run.cpu)
+                                    .collect();
+        cpus.sort();
+        cpus.dedup();
+        cpus
+    }
+}
+
+// runs of every task of a trace, from the switch to it until the next switch on the same cpu
+fn task_runs(filepath: &str) -> (TraceParser, Vec<Run>) {
+    let mut reader = TraceParser::new(filepath);
+    let mut actions: Vec<Action> = Vec::new();
+    while let Some((action, ..)) = reader.next_action() {
+        if let Events::SchedSwitch { .. } = action.event {
+            actions.push(action);
+        }
+    }
+    let orig = reader.start_timestamp.unwrap_or(0.0);
+    let (switch_events, _) = get_sched_switch_events(&actions);
+
+    let mut runs = Vec::new();
+    for (cpu, events) in switch_events {
+        for item in events.windows(2) {
+            if let Events::SchedSwitch { old_command, old_pid, .. } = &item[1].event {
+                if *old_pid == 0 || item[1].timestamp < item[0].timestamp {
+                    continue;
+                }
+                runs.push(Run { command: old_command.clone(), pid: *old_pid, cpu, start: item[0].timestamp - orig, end: item[1].timestamp - orig });
+            }
+        }
+    }
+    (reader, runs)
+}
+
+// category of a candidate run on cpu, by the cpus the same task ran on in the baseline over the same window
+// baseline_cpus is None for a task that is not in the baseline
+fn placement(cpu: u32, baseline_cpus: Option<&[u32]>, machine: &Machine) -> &'static str {
+    let Some(baseline_cpus) = baseline_cpus else {
+        return "new task";
+    };
+    let (socket, _) = get_socket_order(cpu, machine);
+    if baseline_cpus.is_empty() {
+        "not running in baseline"
+    } else if baseline_cpus.contains(&cpu) {
+        "same cpu"
+    } else if baseline_cpus.iter().any(|baseline_cpu| get_socket_order(*baseline_cpu, machine).0 == socket) {
+        "other cpu, same socket"
+    } else {
+        "other socket"
+    }
+}
+
+// Timeline of the candidate trace, every run colored by where the task ran in the baseline trace
+// Both traces are aligned on their first event, and a task is matched by command and pid,
+// or by command alone when the pids changed between the two traces, like for a benchmark started again
+pub fn diff_graph(baseline: &str, candidate: &str, config: &Config) {
+    let options = &config.graph;
+    let baseline_name = baseline.split("/").last().unwrap();
+    let candidate_name = candidate.split("/").last().unwrap();
+
+    let (_, baseline_runs) = task_runs(baseline);
+    let (reader, candidate_runs) = task_runs(candidate);
+    // the runs are compared from the first event of each trace, but drawn and hovered like in the other plots,
+    // with absolute_time and hover_time applied to the candidate timestamps
+    let start = reader.start_timestamp.unwrap_or(0.0);
+    let x_shift = start - time_origin(options, start);
+    let hover_shift = start - hover_origin(options, start);
+
+    let mut by_task: HashMap<(&str, u32), Vec<&Run>> = HashMap::new();
+    let mut by_command: HashMap<&str, Vec<&Run>> = HashMap::new();
+    for run in &baseline_runs {
+        by_task.entry((run.command.as_str(), run.pid)).or_default().push(run);
+        by_command.entry(run.command.as_str()).or_default().push(run);
+    }
+    let by_task: HashMap<(&str, u32), Timeline> = by_task.into_iter().map(|(task, runs)| (task, Timeline::new(runs))).collect();
+    let by_command: HashMap<&str, Timeline> = by_command.into_iter().map(|(command, runs)| (command, Timeline::new(runs))).collect();
+
+    let y_axis = if options.view == "socket" {
+        get_socket_axis(&config.machine, reader.cpu_count, &options.cpu_filter)
+    } else {
+        get_y_axis(&config.machine, options.socket_order, reader.cpu_count, &options.cpu_filter, &options.cpu_order)
+    };
+
+    // the runs of a category are drawn as a single trace, separated by gaps
+    let mut categories: HashMap<&str, Segments> = HashMap::new();
+    let mut totals: HashMap<&str, (usize, f64)> = HashMap::new();
+    for run in &candidate_runs {
+        let timeline = by_task.get(&(run.command.as_str(), run.pid)).or_else(|| by_command.get(run.command.as_str()));
+        let baseline_cpus = timeline.map(|timeline| timeline.cpus(run.start, run.end));
+        let category = placement(run.cpu, baseline_cpus.as_deref(), &config.machine);
+        let total = totals.entry(category).or_default();
+        total.0 += 1;
+        total.1 += run.end - run.start;
+
+        let Some(row) = y_axis.get(&run.cpu) else {
+            continue;
+        };
+        let baseline_text = match &baseline_cpus {
+            Some(cpus) if !cpus.is_empty() => cpus.iter().map(|cpu| cpu.to_string()).collect::<Vec<String>>().join(", "),
+            _ => String::from("none"),
+        };
+        let hover_text = format!("Command: {}<br>Pid: {}<br>Cpu: {}<br>From: {:.*}<br>To: {:.*}<br>Baseline cpus: {}",
+                                    run.command, run.pid, run.cpu, options.timestamp_precision, run.start + hover_shift, options.timestamp_precision, run.end + hover_shift, baseline_text);
+        let (xs, ys, hover) = categories.entry(category).or_default();
+        xs.extend([Some(run.start + x_shift), Some(run.end + x_shift), None]);
+        ys.extend([Some(*row), Some(*row), None]);
+        hover.extend([hover_text.clone(), hover_text, String::new()]);
+    }
+
+    println!("{} against {}:", candidate_name, baseline_name);
+    let mut plot = Plot::new();
+    for (category, color) in CATEGORIES {
+        let (runs, time) = totals.get(category).copied().unwrap_or_default();
+        println!("  {:<28} {} runs, {:.*} seconds", category, runs, options.timestamp_precision, time);
+        let Some((xs, ys, hover)) = categories.remove(category) else {
+            continue;
+        };
+        plot.add_trace(Scatter::new(xs, ys)
+                        .mode(Mode::Lines)
+                        .line(Line::new().color(color).width(options.line_width))
+                        .hover_text_array(hover)
+                        .hover_info(HoverInfo::Text)
+                        .name(category)
+                        .web_gl_mode(options.webgl));
+    }
+
+    let x_axis_title = if options.absolute_time { "Trace time (seconds)" } else { "Time since the first event (seconds)" };
+    let mut layout = Layout::new()
+                        .x_axis(
+                            Axis::new()
+                            .title(Title::new(x_axis_title))
+                            .show_grid(options.show_grid))
+                        .y_axis(get_cpu_axis(config, &reader, &y_axis))
+                        .auto_size(true);
+    if options.show_title {
+        layout = layout.title(Title::new(format!("Diff: {} against {}", candidate_name, baseline_name).as_str()));
+    }
+    plot.set_layout(layout);
+    write_plot(&mut plot, options, &format!("{}-diff", candidate_name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(cpu: u32, start: f64, end: f64) -> Run {
+        Run { command: String::from("bash"), pid: 1000, cpu, start, end }
+    }
+
+    #[test]
+    fn runs_are_placed_against_the_baseline_window() {
+        let machine = Machine { cpus: 4, sockets: 2, cores_per_socket: 2, threads_per_core: 1, numa_nodes: 2, numa_node_ranges: vec![vec![vec![0, 1]], vec![vec![2, 3]]], node_hops: None };
+        let baseline = [run(0, 0.0, 1.0), run(1, 1.0, 2.0), run(3, 5.0, 6.0)];
+        let timeline = Timeline::new(baseline.iter().collect());
+
+        assert_eq!(timeline.cpus(0.5, 1.5), vec![0, 1]);
+        assert_eq!(placement(1, Some(&timeline.cpus(1.2, 1.5)), &machine), "same cpu");
+        assert_eq!(placement(0, Some(&timeline.cpus(1.2, 1.5)), &machine), "other cpu, same socket");
+        assert_eq!(placement(1, Some(&timeline.cpus(5.5, 7.0)), &machine), "other socket");
+        assert_eq!(placement(2, Some(&timeline.cpus(3.0, 4.0)), &machine), "not running in baseline");
+        assert_eq!(placement(2, None, &machine), "new task");
+    }
+}
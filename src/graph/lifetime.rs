@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use crate::graph::parser::*;
+use crate::read_config::Graph;
+use plotly::common::{ DashType, HoverInfo, Line, Mode, Title };
+use plotly::layout::{ Axis, Layout };
+use plotly::color::Rgb;
+use plotly::{ Plot, Scatter };
+use super::{ color_by_parent, hover_origin, reserved_colors, time_origin, ColorTable };
+
+// Lifespan of a task, from its fork to its exit
+// The first and last time the task was seen running bound the unknown ends
+struct Lifetime {
+    command: String,
+    fork: Option<f64>,
+    exit: Option<f64>,
+    first_seen: f64,
+    last_seen: f64,
+}
+
+// Collects the lifetimes of the tasks forking or exiting in the trace
+#[derive(Default)]
+pub struct Lifetimes {
+    tasks: HashMap<u32, Lifetime>,
+}
+
+impl Lifetimes {
+    fn task(&mut self, pid: u32, command: &str, timestamp: f64) -> &mut Lifetime {
+        let task = self.tasks.entry(pid).or_insert_with(|| Lifetime {
+            command: command.to_string(),
+            fork: None,
+            exit: None,
+            first_seen: timestamp,
+            last_seen: timestamp,
+        });
+        task.first_seen = task.first_seen.min(timestamp);
+        task.last_seen = task.last_seen.max(timestamp);
+        task
+    }
+
+    // update the lifetimes from an action of the trace
+    pub fn record(&mut self, action: &Action) {
+        match &action.event {
+            Events::SchedProcessFork { child_command, child_pid, .. } => {
+                self.task(*child_pid, child_command, action.timestamp).fork = Some(action.timestamp);
+            }
+            Events::SchedProcessExit { command, pid } => {
+                self.task(*pid, command, action.timestamp).exit = Some(action.timestamp);
+            }
+            _ => {
+                // only tasks with a fork or exit in the trace get a bar
+                if let Some(task) = self.tasks.get_mut(&action.pid) {
+                    task.first_seen = task.first_seen.min(action.timestamp);
+                    task.last_seen = task.last_seen.max(action.timestamp);
+                }
+            }
+        }
+    }
+}
+
+// draw a segment of a lifetime bar, dashed when the end of the lifetime is outside the trace
+fn add_bar(plot: &mut Plot, start: f64, end: f64, row: usize, color: Rgb, dashed: bool, hover_text: &str, webgl: bool) {
+    let mut line = Line::new().color(color).width(4.0);
+    if dashed {
+        line = line.dash(DashType::Dash);
+    }
+    plot.add_trace(Scatter::new(vec![start, end], vec![row, row])
+        .mode(Mode::Lines)
+        .line(line)
+        .hover_text(hover_text)
+        .hover_info(HoverInfo::Text)
+        .web_gl_mode(webgl)
+        .show_legend(false));
+}
+
+// One bar per task from its fork to its exit, colored by parent
+// Tasks forked before the trace or still alive at its end extend to the plot boundary with a dashed cap
+pub fn draw_lifetimes(plot: &mut Plot, lifetimes: &Lifetimes, fork_events: &Vec<Action>, first_timestamp: f64, last_timestamp: f64, options: &Graph, filename: &str) {
+    let colors = match color_by_parent(fork_events, &reserved_colors(options)) {
+        ColorTable::Parent(colors) => colors,
+        _ => HashMap::new(),
+    };
+
+    let mut tasks: Vec<(&u32, &Lifetime)> = lifetimes.tasks.iter().collect();
+    tasks.sort_by(|a, b| a.1.fork.unwrap_or(first_timestamp).total_cmp(&b.1.fork.unwrap_or(first_timestamp)).then(a.0.cmp(b.0)));
+
+    let orig = time_origin(options, first_timestamp);
+    let hover_orig = hover_origin(options, first_timestamp);
+    for (row, (pid, task)) in tasks.iter().enumerate() {
+        let color = colors.get(pid).copied().unwrap_or(Rgb::new(128, 128, 128));
+        let hover_text = format!("Command: {}<br>Pid: {}<br>Fork: {}<br>Exit: {}",
+                                    task.command, pid,
+                                    task.fork.map_or(String::from("before trace"), |t| format!("{:.*}", options.timestamp_precision, t - hover_orig)),
+                                    task.exit.map_or(String::from("after trace"), |t| format!("{:.*}", options.timestamp_precision, t - hover_orig)));
+
+        let start = task.fork.unwrap_or(task.first_seen);
+        let end = task.exit.unwrap_or(task.last_seen);
+        add_bar(plot, start - orig, end - orig, row, color, false, &hover_text, options.webgl);
+
+        if task.fork.is_none() {
+            add_bar(plot, first_timestamp - orig, start - orig, row, color, true, &hover_text, options.webgl);
+        }
+        if task.exit.is_none() {
+            add_bar(plot, end - orig, last_timestamp - orig, row, color, true, &hover_text, options.webgl);
+        }
+    }
+
+    let mut layout = Layout::new()
+                        .x_axis(
+                            Axis::new()
+                            .title(Title::new(&format!("Duration: {:.*} seconds", options.timestamp_precision, last_timestamp - first_timestamp)))
+                            .range(vec![first_timestamp - orig, last_timestamp - orig])
+                            .show_grid(false))
+                        .y_axis(
+                            Axis::new()
+                            .title(Title::new("Tasks"))
+                            .show_grid(false))
+                        .auto_size(true);
+    if options.show_title {
+        layout = layout.title(Title::new(format!("Task lifetimes: {}", filename).as_str()));
+    }
+    plot.set_layout(layout);
+}
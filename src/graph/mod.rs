@@ -1,12 +1,33 @@
 pub mod parser;
+pub mod lifetime;
+pub mod combine;
+pub mod wstate;
+pub mod task_state;
+pub mod preview;
+pub mod stats;
+pub mod occupancy;
+pub mod diff;
+pub mod benchmark;
 use rand::Rng;
-use std::collections::HashMap;
-use crate::parser::*;
+use regex::Regex;
+use std::time::{ Duration, Instant };
+use std::collections::{ HashMap, HashSet };
+use std::hash::Hash;
+use std::process::exit;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use crate::graph::parser::*;
+use lifetime::*;
 use crate::read_config::{ Config, Machine, Graph };
-use plotly::common::{ Line, Marker, Mode, Title, MarkerSymbol, HoverInfo};
-use plotly::layout::{ Axis, Layout };
-use plotly::{ Scatter, Plot, ImageFormat, Configuration, Trace };
-use plotly::color::{ Rgb, NamedColor };
+use plotly::common::{ Anchor, AxisSide, ColorScale, ColorScalePalette, DashType, Font, Line, LineShape, Marker, Mode, Title, MarkerSymbol, HoverInfo};
+use plotly::layout::{ Annotation, Axis, AxisType, Layout, RangeMode, Shape, ShapeLine, ShapeType };
+use plotly::{ HeatMap, Scatter, Plot, ImageFormat, Configuration, Trace };
+use plotly::color::{ Color, Rgb, NamedColor };
+use chrono::DateTime;
+use sha2::{ Digest, Sha256 };
+use std::fs::File;
+use std::io;
+use serde::Serialize;
+use serde_json::Value;
 
 // Scatter object to store notch-only events
 // Drawing all such events at once is more efficient than adding their trace individually
@@ -15,57 +36,191 @@ struct ScatterObject {
     ys: Vec<u32>,
     mode: Mode,
     name: String,
-    color: NamedColor,
+    color: Rgb,
+    symbol: MarkerSymbol,
     color_array: Vec<Rgb>,
     hover_text: Vec<String>,
 }
 
 impl ScatterObject {
-    fn new(mode: Mode, name: &str, color: NamedColor) -> Self {
+    fn new(mode: Mode, name: &str, color: Rgb) -> Self {
         ScatterObject {
             xs: Vec::new(),
             ys: Vec::new(),
             mode,
             name: name.to_string(),
             color,
+            symbol: MarkerSymbol::LineNSOpen,
             color_array: Vec::new(),
             hover_text: Vec::new(),
         }
     }
 }
 
-// constructs a Hashmap for events containing only a notch
-// Adding events: insert an event and its color here
-//      followed by adding its match condition in draw_traces()
-fn marker_events_object() -> HashMap<String, ScatterObject> {
+// A trace edited as json, e.g. moved to the axes of a subplot
+#[derive(Clone, Serialize)]
+#[serde(transparent)]
+pub(crate) struct JsonTrace(pub(crate) Value);
+
+impl Trace for JsonTrace {
+    fn to_json(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+// How an event is drawn, which decides its legend and when it is shown
+#[derive(Clone)]
+enum EventCategory {
+    // a notch, or another symbol, on the cpu row, shown with show_marker_only
+    Marker(MarkerSymbol),
+    // a line between the cpus and a notch, shown with show_migrate
+    Migrate,
+    // a distinct symbol on the cpus involved, shown with show_migrate
+    Numa(MarkerSymbol),
+    // a thin tick at the bottom of the cpu row, shown with show_irq
+    Irq,
+}
+
+// Every event of the plot with its color, task colors are kept away from these
+// Adding events: insert an event here, followed by adding its match condition in draw_traces()
+const EVENTS: [(&str, NamedColor, EventCategory); 18] = [
+    ("wakeup", NamedColor::RoyalBlue, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("wakeup new", NamedColor::Brown, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("wake idle no ipi", NamedColor::LimeGreen, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("waking", NamedColor::DarkOliveGreen, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("process fork", NamedColor::Pink, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("process exec", NamedColor::Purple, EventCategory::Marker(MarkerSymbol::LineNSOpen)),
+    ("enter D-state", NamedColor::Black, EventCategory::Marker(MarkerSymbol::Hourglass)),
+    ("pi setprio", NamedColor::Magenta, EventCategory::Marker(MarkerSymbol::Pentagon)),
+    ("on-socket<br>unblock placement", NamedColor::DeepPink, EventCategory::Migrate),
+    ("off-socket<br>unblock placement", NamedColor::SkyBlue, EventCategory::Migrate),
+    ("numa balancing", NamedColor::SeaGreen, EventCategory::Migrate),
+    ("on-socket<br>load balancing", NamedColor::Gold, EventCategory::Migrate),
+    ("off-socket<br>load balancing", NamedColor::Orange, EventCategory::Migrate),
+    ("numa swap", NamedColor::Teal, EventCategory::Numa(MarkerSymbol::Diamond)),
+    ("numa stick", NamedColor::Crimson, EventCategory::Numa(MarkerSymbol::X)),
+    ("numa move", NamedColor::DarkViolet, EventCategory::Numa(MarkerSymbol::Star)),
+    ("irq", NamedColor::Chocolate, EventCategory::Irq),
+    ("softirq", NamedColor::SlateBlue, EventCategory::Irq),
+];
+
+// Symbols that can be set for an event in event_symbols
+const SYMBOLS: [MarkerSymbol; 28] = [
+    MarkerSymbol::Circle, MarkerSymbol::CircleOpen, MarkerSymbol::Square, MarkerSymbol::SquareOpen,
+    MarkerSymbol::Diamond, MarkerSymbol::DiamondOpen, MarkerSymbol::Cross, MarkerSymbol::CrossOpen,
+    MarkerSymbol::X, MarkerSymbol::XOpen, MarkerSymbol::TriangleUp, MarkerSymbol::TriangleUpOpen,
+    MarkerSymbol::TriangleDown, MarkerSymbol::TriangleDownOpen, MarkerSymbol::TriangleLeft, MarkerSymbol::TriangleRight,
+    MarkerSymbol::Pentagon, MarkerSymbol::PentagonOpen, MarkerSymbol::Hexagon, MarkerSymbol::HexagonOpen,
+    MarkerSymbol::Star, MarkerSymbol::StarOpen, MarkerSymbol::Hourglass, MarkerSymbol::BowTie,
+    MarkerSymbol::AsteriskOpen, MarkerSymbol::HashOpen, MarkerSymbol::LineNSOpen, MarkerSymbol::LineEWOpen,
+];
+
+// plotly name of a symbol, e.g. line-ns-open
+fn symbol_name(symbol: &MarkerSymbol) -> String {
+    serde_json::to_value(symbol).ok().and_then(|name| name.as_str().map(String::from)).unwrap_or_default()
+}
+
+// symbol from its plotly name, case and dashes are ignored so both triangle-up and TriangleUp work
+fn parse_symbol(name: &str) -> Option<MarkerSymbol> {
+    let key = |name: &str| name.to_lowercase().replace('-', "");
+    SYMBOLS.into_iter().find(|symbol| key(&symbol_name(symbol)) == key(name))
+}
+
+// symbol of every event drawn with one, the event_symbols of the config replace those of EVENTS
+// Migrations keep their triangles, which point in the direction of the migration
+fn event_symbols(options: &Graph) -> HashMap<String, MarkerSymbol> {
+    let mut symbols = HashMap::new();
+    for (name, _, category) in EVENTS {
+        match category {
+            EventCategory::Marker(symbol) | EventCategory::Numa(symbol) => { symbols.insert(name.to_string(), symbol); }
+            EventCategory::Irq => { symbols.insert(name.to_string(), MarkerSymbol::LineNSOpen); }
+            EventCategory::Migrate => { }
+        }
+    }
+
+    for (name, symbol) in &options.event_symbols {
+        if !symbols.contains_key(name) {
+            let mut names: Vec<&String> = symbols.keys().collect();
+            names.sort();
+            panic!("Cannot set the symbol of event {}, events with a symbol: {:?}", name, names);
+        }
+        let symbol = parse_symbol(symbol).unwrap_or_else(|| {
+            let names: Vec<String> = SYMBOLS.iter().map(symbol_name).collect();
+            panic!("Unknown symbol {} for event {}, available symbols: {}", symbol, name, names.join(", "))
+        });
+        symbols.insert(name.clone(), symbol);
+    }
+    symbols
+}
+
+// rgb of every event, the event_colors of the config replace those of EVENTS
+// Names are the legend names with spaces for their line breaks, like in exclude_events, and colors any css color
+fn event_rgbs(options: &Graph) -> HashMap<String, (u8, u8, u8)> {
+    let mut colors: HashMap<String, (u8, u8, u8)> = EVENTS.into_iter().map(|(name, color, _)| (name.to_string(), rgb_of(color))).collect();
+    for (name, color) in &options.event_colors {
+        let Some((event, ..)) = EVENTS.iter().find(|(event, ..)| event.replace("<br>", " ") == *name) else {
+            let names: Vec<String> = EVENTS.iter().map(|(name, ..)| name.replace("<br>", " ")).collect();
+            panic!("Cannot set the color of event {}, events: {:?}", name, names);
+        };
+        let rgb = csscolorparser::parse(color).unwrap_or_else(|error| panic!("Invalid color {} for event {}: {}", color, name, error)).to_rgba8();
+        colors.insert(event.to_string(), (rgb[0], rgb[1], rgb[2]));
+    }
+    colors
+}
+
+// color of every event, as drawn
+fn event_colors(options: &Graph) -> HashMap<String, Rgb> {
+    event_rgbs(options).into_iter().map(|(name, (r, g, b))| (name, Rgb::new(r, g, b))).collect()
+}
+
+// Names accepted by exclude_events: the events of EVENTS with spaces for their line breaks,
+// "migrate" for every migration whatever migrate_color groups them by, and "cpu idle" for the idle bands
+fn check_exclude_events(options: &Graph) {
+    let mut names: Vec<String> = EVENTS.iter().map(|(name, ..)| name.replace("<br>", " ")).collect();
+    names.extend([String::from("migrate"), String::from("cpu idle")]);
+    for name in &options.events.exclude_events {
+        if !names.contains(name) {
+            panic!("Cannot exclude event {}, events: {:?}", name, names);
+        }
+    }
+}
+
+// whether an event, by its legend group, is in exclude_events
+fn excluded(options: &Graph, legend_group: &str) -> bool {
+    let name = legend_group.replace("<br>", " ");
+    let migrate = name.contains("placement") || name.contains("balancing") || name.starts_with("migrate ");
+    options.events.exclude_events.iter().any(|excluded| *excluded == name || (migrate && excluded == "migrate"))
+}
+
+// constructs a Hashmap for events containing only a notch, or their symbol from event_symbols
+fn marker_events_object(symbols: &HashMap<String, MarkerSymbol>, colors: &HashMap<String, Rgb>) -> HashMap<String, ScatterObject> {
     let mut map: HashMap<String, ScatterObject> = HashMap::new();
-    let events = vec![("wakeup", NamedColor::RoyalBlue),
-                            ("wakeup new", NamedColor::Brown),
-                            ("wake idle no ipi", NamedColor::LimeGreen),
-                            ("waking", NamedColor::DarkOliveGreen),
-                            ("process fork", NamedColor::Pink)];
+    for (name, _, category) in EVENTS {
+        if let EventCategory::Marker(..) = category {
+            let mut event = ScatterObject::new(Mode::Markers, name, colors[name]);
+            event.symbol = symbols[name].clone();
+            map.insert(name.to_string(), event);
+        }
+    }
+    map
+}
 
-    for (name, color) in events {
-        map.insert(name.to_string(), ScatterObject::new(Mode::Markers, name, color));
+// constructs a Hashmap for the interrupt events
+fn irq_events_object(symbols: &HashMap<String, MarkerSymbol>, colors: &HashMap<String, Rgb>) -> HashMap<String, ScatterObject> {
+    let mut map: HashMap<String, ScatterObject> = HashMap::new();
+    for (name, _, category) in EVENTS {
+        if let EventCategory::Irq = category {
+            let mut event = ScatterObject::new(Mode::Markers, name, colors[name]);
+            event.symbol = symbols[name].clone();
+            map.insert(name.to_string(), event);
+        }
     }
     map
 }
 
 fn get_frequency_map() -> HashMap<String, u32> {
-    let events = vec![
-        "wakeup",
-        "wakeup new",
-        "wake idle no ipi",
-        "waking",
-        "process fork",
-        "on-socket<br>unblock placement",
-        "off-socket<br>unblock placement",
-        "on-socket<br>load balancing",
-        "off-socket<br>load balancing",
-        "numa balancing"
-    ];
     let mut frequency: HashMap<String, u32> = HashMap::new();
-    for event in events {
+    for (event, ..) in EVENTS {
         frequency.insert(event.to_string(), 0);
     }
     frequency
@@ -86,9 +241,70 @@ fn set_marker_size(cpu_count: u32) -> usize{
 }
 
 
-// Get a random color
-fn random_color() -> Rgb {
-    Rgb::new(rand::thread_rng().gen_range(0..=255), rand::thread_rng().gen_range(0..=255), rand::thread_rng().gen_range(0..=255))
+// Minimum CIE76 distance between a task color and the event colors
+// Around 2.3 is just noticeable, so this keeps task segments well apart from the markers
+const MIN_COLOR_DISTANCE: f64 = 20.0;
+
+// rgb values of the reserved event colors
+fn rgb_of(color: NamedColor) -> (u8, u8, u8) {
+    match color {
+        NamedColor::RoyalBlue => (65, 105, 225),
+        NamedColor::Brown => (165, 42, 42),
+        NamedColor::LimeGreen => (50, 205, 50),
+        NamedColor::DarkOliveGreen => (85, 107, 47),
+        NamedColor::Pink => (255, 192, 203),
+        NamedColor::Purple => (128, 0, 128),
+        NamedColor::DeepPink => (255, 20, 147),
+        NamedColor::SkyBlue => (135, 206, 235),
+        NamedColor::SeaGreen => (46, 139, 87),
+        NamedColor::Gold => (255, 215, 0),
+        NamedColor::Orange => (255, 165, 0),
+        NamedColor::Teal => (0, 128, 128),
+        NamedColor::Crimson => (220, 20, 60),
+        NamedColor::DarkViolet => (148, 0, 211),
+        NamedColor::Black => (0, 0, 0),
+        NamedColor::Magenta => (255, 0, 255),
+        NamedColor::Chocolate => (210, 105, 30),
+        NamedColor::SlateBlue => (106, 90, 205),
+        _ => panic!("No rgb value for {:?}", color),
+    }
+}
+
+// convert an sRGB color to CIELAB, with a D65 white point
+fn to_lab((r, g, b): (u8, u8, u8)) -> (f64, f64, f64) {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    (116.0 * f(y) - 16.0, 500.0 * (f(x) - f(y)), 200.0 * (f(y) - f(z)))
+}
+
+// perceptual distance between two colors (CIE76 delta E)
+fn color_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> f64 {
+    let (l1, a1, b1) = to_lab(a);
+    let (l2, a2, b2) = to_lab(b);
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+// the colors used by the events, with those of event_colors, that a task should not be drawn with
+fn reserved_colors(options: &Graph) -> Vec<(u8, u8, u8)> {
+    event_rgbs(options).into_values().collect()
+}
+
+// Get a random color, regenerated while it is too close to an event color
+fn random_color(reserved: &[(u8, u8, u8)]) -> Rgb {
+    loop {
+        let color = (rand::thread_rng().gen_range(0..=255), rand::thread_rng().gen_range(0..=255), rand::thread_rng().gen_range(0..=255));
+        if reserved.iter().all(|event_color| color_distance(color, *event_color) >= MIN_COLOR_DISTANCE) {
+            return Rgb::new(color.0, color.1, color.2);
+        }
+    }
 }
 
 
@@ -97,66 +313,151 @@ enum ColorTable {
     Command(HashMap<String, Rgb>),
     Parent(HashMap<u32, Rgb>),
     Pid(HashMap<u32, Rgb>),
+    // colors by the key cgroup_regex extracts from the command
+    Cgroup(Regex, HashMap<String, Rgb>),
+    // the color of a segment only depends on the priority of the task at the switch
+    Prio,
 }
 
-fn color_by_pid(actions: &Vec<Action>) -> ColorTable {
-    let mut colors: HashMap<u32, Rgb> = HashMap::new();
+// shared by the tasks past max_colors, and the tasks without a priority with color_by prio
+const OTHER_COLOR: (u8, u8, u8) = (128, 128, 128);
+
+// priorities past the normal ones, of the idle scheduling class
+const IDLE_CLASS_COLOR: (u8, u8, u8) = (140, 100, 60);
+
+// Color of a priority class, real-time priorities 0 to 99 go from dark red to yellow,
+// normal ones 100 to 139 from dark to light blue, so real-time tasks stand out among the others
+fn prio_color(prio: Option<u32>) -> Rgb {
+    let gradient = |from: (f64, f64, f64), to: (f64, f64, f64), ratio: f64| {
+        Rgb::new((from.0 + (to.0 - from.0) * ratio) as u8, (from.1 + (to.1 - from.1) * ratio) as u8, (from.2 + (to.2 - from.2) * ratio) as u8)
+    };
+    match prio {
+        Some(prio) if prio < 100 => gradient((139.0, 0.0, 0.0), (255.0, 215.0, 0.0), prio as f64 / 99.0),
+        Some(prio) if prio < 140 => gradient((8.0, 48.0, 107.0), (158.0, 202.0, 225.0), (prio - 100) as f64 / 39.0),
+        Some(_) => Rgb::new(IDLE_CLASS_COLOR.0, IDLE_CLASS_COLOR.1, IDLE_CLASS_COLOR.2),
+        None => Rgb::new(OTHER_COLOR.0, OTHER_COLOR.1, OTHER_COLOR.2),
+    }
+}
+
+// A random color for the max_colors keys in the most switches, the others share OTHER_COLOR
+// Ties are broken by key, so the same keys get their own color on every run
+fn ranked_colors<K: Ord + Hash + Clone>(counts: HashMap<K, usize>, max_colors: Option<usize>, reserved: &[(u8, u8, u8)]) -> HashMap<K, Rgb> {
+    let mut ranked: Vec<(K, usize)> = counts.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let distinct = max_colors.unwrap_or(ranked.len());
+    ranked.into_iter().enumerate()
+        .map(|(rank, (key, _))| (key, if rank < distinct { random_color(reserved) } else { Rgb::new(OTHER_COLOR.0, OTHER_COLOR.1, OTHER_COLOR.2) }))
+        .collect()
+}
+
+fn color_by_pid(actions: &Vec<Action>, max_colors: Option<usize>, reserved: &[(u8, u8, u8)]) -> ColorTable {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
     for action in actions {
-        if let Events::SchedSwitch { old_command: _, old_pid, state: _, new_command: _, new_pid } = &action.event {
+        if let Events::SchedSwitch { old_pid, new_pid, .. } = &action.event {
             if *old_pid != 0 {
-                if let None = colors.get(&old_pid) {
-                    colors.insert(*old_pid, random_color());
-                }
+                *counts.entry(*old_pid).or_insert(0) += 1;
             }
             if *new_pid != 0 {
-                if let None = colors.get(&new_pid) {
-                    colors.insert(*new_pid, random_color());
-                }
+                *counts.entry(*new_pid).or_insert(0) += 1;
             }
         }
     }
-    ColorTable::Pid(colors)
+    ColorTable::Pid(ranked_colors(counts, max_colors, reserved))
 }
 
-fn color_by_command(actions: &Vec<Action>) -> ColorTable {
-    let mut colors: HashMap<String, Rgb> = HashMap::new();
+fn color_by_command(actions: &Vec<Action>, max_colors: Option<usize>, reserved: &[(u8, u8, u8)]) -> ColorTable {
+    let mut counts: HashMap<String, usize> = HashMap::new();
     for action in actions {
-        if let Events::SchedSwitch { old_command, old_pid, state: _, new_command, new_pid } = &action.event {
+        if let Events::SchedSwitch { old_command, old_pid, new_command, new_pid, .. } = &action.event {
             if *old_pid != 0 {
-                if let None = colors.get(old_command) {
-                    colors.insert(old_command.clone(), random_color());
-                }
+                *counts.entry(old_command.clone()).or_insert(0) += 1;
             }
             if *new_pid != 0 {
-                if let None = colors.get(new_command) {
-                    colors.insert(new_command.clone(), random_color());
-                }
+                *counts.entry(new_command.clone()).or_insert(0) += 1;
             }
         }
     }
-    ColorTable::Command(colors)
+    ColorTable::Command(ranked_colors(counts, max_colors, reserved))
 }
 
-fn color_by_parent(actions: &Vec<Action>) -> ColorTable {
-    let mut colors: HashMap<u32, Rgb> = HashMap::new();
+// group of a command, the first capture group of cgroup_regex or its whole match when it has none
+// commands the regex does not match are all in the same group
+fn cgroup_key(regex: &Regex, command: &str) -> String {
+    match regex.captures(command) {
+        Some(captures) => captures.get(1).or(captures.get(0)).map_or("", |key| key.as_str()).to_string(),
+        None => String::from("ungrouped"),
+    }
+}
+
+fn color_by_cgroup(actions: &Vec<Action>, regex: Regex, max_colors: Option<usize>, reserved: &[(u8, u8, u8)]) -> ColorTable {
+    let mut counts: HashMap<String, usize> = HashMap::new();
     for action in actions {
-        if let Events::SchedProcessFork { pid, child_pid, .. } = &action.event {
-            if let None = colors.get(pid) {
-                colors.insert(*pid, random_color());
+        if let Events::SchedSwitch { old_command, old_pid, new_command, new_pid, .. } = &action.event {
+            if *old_pid != 0 {
+                *counts.entry(cgroup_key(&regex, old_command)).or_insert(0) += 1;
             }
-            if let None = colors.get(child_pid) {
-                colors.insert(*child_pid, colors[pid]);
+            if *new_pid != 0 {
+                *counts.entry(cgroup_key(&regex, new_command)).or_insert(0) += 1;
             }
         }
-        else if let None = colors.get(&action.pid) {
-            colors.insert(action.pid, random_color());
+    }
+    ColorTable::Cgroup(regex, ranked_colors(counts, max_colors, reserved))
+}
+
+fn color_by_parent(actions: &Vec<Action>, reserved: &[(u8, u8, u8)]) -> ColorTable {
+    let mut colors: HashMap<u32, Rgb> = HashMap::new();
+    for action in actions {
+        if let Events::SchedProcessFork { pid, child_pid, .. } = &action.event {
+            let parent_color = *colors.entry(*pid).or_insert_with(|| random_color(reserved));
+            colors.entry(*child_pid).or_insert(parent_color);
+        }
+        else {
+            colors.entry(action.pid).or_insert_with(|| random_color(reserved));
         }
     }
     ColorTable::Parent(colors)
 }
 
+// Thread group leader of every forked task, as the trace has no tgid it is approximated from the fork tree:
+// a task is in the group of its parent unless it exec'd, which makes it a leader since threads do not exec
+// Tasks without a fork in the trace are their own leader
+fn thread_groups(fork_events: &[Action], exec_pids: &HashSet<u32>) -> HashMap<u32, u32> {
+    let parents: HashMap<u32, u32> = fork_events.iter().filter_map(|action| match &action.event {
+        Events::SchedProcessFork { pid, child_pid, .. } => Some((*child_pid, *pid)),
+        _ => None,
+    }).collect();
+
+    parents.keys().map(|pid| {
+        let mut leader = *pid;
+        // reused pids can loop the tree, no chain is longer than the number of forks
+        for _ in 0..parents.len() {
+            match parents.get(&leader) {
+                Some(parent) if !exec_pids.contains(&leader) => leader = *parent,
+                _ => break,
+            }
+        }
+        (*pid, leader)
+    }).collect()
+}
+
+// every thread takes the color of its group leader, colors by command or cgroup are left as they are
+fn group_colors(color_table: ColorTable, leaders: &HashMap<u32, u32>) -> ColorTable {
+    let regroup = |mut colors: HashMap<u32, Rgb>| {
+        for (pid, leader) in leaders {
+            if let Some(color) = colors.get(leader).copied() {
+                colors.insert(*pid, color);
+            }
+        }
+        colors
+    };
+    match color_table {
+        ColorTable::Pid(colors) => ColorTable::Pid(regroup(colors)),
+        ColorTable::Parent(colors) => ColorTable::Parent(regroup(colors)),
+        command => command,
+    }
+}
 
-// find the socket for the given cpu
+// find the socket for the given cpu, and the index of its range in the socket
 fn get_socket_order(cpu: u32, machine: &Machine) -> (u32, u32) {
     for (socket_id, socket_ranges) in machine.numa_node_ranges.iter().enumerate() {
         for (range_id, range) in socket_ranges.iter().enumerate() {
@@ -165,43 +466,153 @@ fn get_socket_order(cpu: u32, machine: &Machine) -> (u32, u32) {
             }
         }
     }
-    panic!("Bad numa node ranges in config");
+    // cpus outside of the machine config, like hotplugged ones, are put in a socket of their own
+    (machine.numa_node_ranges.len() as u32, 0)
 }
 
 // If socket_order = true, transform the y-axis to have cpus in the same socket together
+// If cpu_order is not empty, its cpus get the first rows in order and the other cpus follow, whatever socket_order is
+// If cpu_filter is not empty, only the selected cpus get a row
 // Can then be used for the y-value of any point
-fn get_y_axis(machine: &Machine, socket_order: bool, cpu_count: u32) -> HashMap<u32, u32> {
+fn get_y_axis(machine: &Machine, socket_order: bool, cpu_count: u32, cpu_filter: &[u32], cpu_order: &[u32]) -> HashMap<u32, u32> {
     let mut y_axis = HashMap::new();
 
-    if !socket_order {
+    if !cpu_order.is_empty() {
+        let unlisted = (0..cpu_count).filter(|cpu| !cpu_order.contains(cpu));
+        for (row, cpu) in cpu_order.iter().copied().chain(unlisted).enumerate() {
+            y_axis.insert(cpu, row as u32);
+        }
+        // listed cpus that are not in the trace leave no gap
+        let keep = |cpu: u32| cpu < cpu_count && (cpu_filter.is_empty() || cpu_filter.contains(&cpu));
+        return compress_y_axis(y_axis, keep);
+    }
+    else if !socket_order {
         for cpu in 0..cpu_count {
             y_axis.insert(cpu, cpu);
         }
+    }
+    else {
+        // rows are given in the order of the numa node ranges, so the cpus of a socket are together whatever the size of
+        // its ranges, cpus outside of the ranges follow in natural order
+        let ranges = machine.numa_node_ranges.iter().flatten().filter_map(|range| match range[..] {
+            [start, end] => Some(start..=end),
+            _ => None,
+        });
+        for cpu in ranges.flatten().chain(0..machine.cpus).filter(|cpu| *cpu < machine.cpus) {
+            let row = y_axis.len() as u32;
+            y_axis.entry(cpu).or_insert(row);
+        }
+    }
+
+    if cpu_filter.is_empty() {
         return y_axis;
     }
+    compress_y_axis(y_axis, |cpu| cpu_filter.contains(&cpu))
+}
 
-    for cpu in 0..machine.cpus {
-        let (socket, range) = get_socket_order(cpu, machine);
-        let cores_per_socket = machine.cores_per_socket;
-        let cpu_within_socket = cpu % cores_per_socket;
-        let socket_offset = socket * cores_per_socket * machine.threads_per_core;
-        let y_axis_cpu_position = socket_offset + cpu_within_socket + range * cores_per_socket;
-        y_axis.insert(cpu, y_axis_cpu_position);
+// socket view: every cpu is mapped to the row of its socket
+fn get_socket_axis(machine: &Machine, cpu_count: u32, cpu_filter: &[u32]) -> HashMap<u32, u32> {
+    let mut y_axis = HashMap::new();
+    for cpu in 0..cpu_count {
+        if cpu_filter.is_empty() || cpu_filter.contains(&cpu) {
+            let (socket, _) = get_socket_order(cpu, machine);
+            y_axis.insert(cpu, socket);
+        }
     }
     y_axis
 }
 
+// keep only the cpus for which keep() is true, renumbering the rows without gaps
+// The relative order of the kept rows is preserved, so socket grouping still holds
+fn compress_y_axis(y_axis: HashMap<u32, u32>, keep: impl Fn(u32) -> bool) -> HashMap<u32, u32> {
+    let mut rows: Vec<(u32, u32)> = y_axis.into_iter().filter(|(cpu, _)| keep(*cpu)).collect();
+    rows.sort_by_key(|(_, row)| *row);
+    rows.into_iter().enumerate().map(|(row, (cpu, _))| (cpu, row as u32)).collect()
+}
+
+// tick positions and labels showing the real cpu number of each row
+// With a step, only the rows 0, step, 2 * step... get a tick, counted from the first row of every socket with socket_order
+fn cpu_tick_labels(y_axis: &HashMap<u32, u32>, machine: &Machine, socket_order: bool, step: u32) -> (Vec<f64>, Vec<String>) {
+    let mut socket_start: HashMap<u32, u32> = HashMap::new();
+    if socket_order {
+        for (cpu, row) in y_axis {
+            let (socket, _) = get_socket_order(*cpu, machine);
+            let start = socket_start.entry(socket).or_insert(*row);
+            *start = (*start).min(*row);
+        }
+    }
+    let first_row = |cpu: u32| if socket_order { socket_start[&get_socket_order(cpu, machine).0] } else { 0 };
+
+    let mut rows: Vec<(u32, u32)> = y_axis.iter().map(|(cpu, row)| (*row, *cpu)).collect();
+    rows.sort();
+    rows.into_iter()
+        .filter(|(row, cpu)| step == 0 || (row - first_row(*cpu)).is_multiple_of(step))
+        .map(|(row, cpu)| (row as f64, cpu.to_string()))
+        .unzip()
+}
+
+// cpus the action is drawn on
+fn action_cpus(action: &Action) -> Vec<u32> {
+    match &action.event {
+        Events::SchedMigrateTask { orig_cpu, dest_cpu, .. } => vec![action.cpu, *orig_cpu, *dest_cpu],
+        Events::SchedSwapNuma { src_cpu, dst_cpu, .. } |
+        Events::SchedStickNuma { src_cpu, dst_cpu, .. } |
+        Events::SchedMoveNuma { src_cpu, dst_cpu, .. } => {
+            let mut cpus = vec![action.cpu];
+            cpus.extend([*src_cpu, *dst_cpu].iter().filter(|cpu| **cpu >= 0).map(|cpu| *cpu as u32));
+            cpus
+        }
+        _ => vec![action.cpu],
+    }
+}
+
+// whether every cpu the action is drawn on has a row on the y-axis
+fn on_displayed_cpus(action: &Action, y_axis: &HashMap<u32, u32>) -> bool {
+    action_cpus(action).iter().all(|cpu| y_axis.contains_key(cpu))
+}
+
+// give a row to a cpu missing from the y-axis, e.g. past the cpus= header after a hotplug
+// It goes below the other cpus, or on the row of its socket in the socket view
+// returns whether a row was added
+fn add_unexpected_cpu(y_axis: &mut HashMap<u32, u32>, cpu: u32, machine: &Machine, view: &str) -> bool {
+    if y_axis.contains_key(&cpu) {
+        return false;
+    }
+    let row = match view {
+        "socket" => get_socket_order(cpu, machine).0,
+        _ => y_axis.values().max().map_or(0, |row| row + 1),
+    };
+    y_axis.insert(cpu, row);
+    true
+}
+
+
+// whether two switch events are the same line repeated, as found in some merged traces
+fn is_duplicate_switch(previous: &Action, action: &Action) -> bool {
+    match (&previous.event, &action.event) {
+        (Events::SchedSwitch { old_pid, new_pid, .. }, Events::SchedSwitch { old_pid: pid, new_pid: next_pid, .. }) => {
+            previous.timestamp == action.timestamp && old_pid == pid && new_pid == next_pid
+        }
+        _ => false
+    }
+}
 
 // group the switch events by cpu, order is the same as the input vector
-fn get_sched_switch_events(actions: &Vec<Action>) -> HashMap<u32, Vec<&Action>> {
+// A switch repeating the one before it on the same cpu is dropped, the number dropped is returned
+fn get_sched_switch_events(actions: &Vec<Action>) -> (HashMap<u32, Vec<&Action>>, usize) {
     let mut data: HashMap<u32, Vec<&Action>> = HashMap::new();
+    let mut duplicates = 0;
     for action in actions {
         if let Events::SchedSwitch { .. } = &action.event {
-            let entry = data.entry(action.cpu).or_insert_with(Vec::new);
+            let entry = data.entry(action.cpu).or_default();
+            if entry.last().is_some_and(|previous| is_duplicate_switch(previous, action)) {
+                duplicates += 1;
+                continue;
+            }
             entry.push(action);
         }
     }
-    data
+    (data, duplicates)
 }
 
 fn draw_switch_markers(plot: &mut Plot, switch_markers: ScatterObject, options: &Graph, marker_size: usize) {
@@ -229,35 +640,129 @@ fn draw_switch_markers(plot: &mut Plot, switch_markers: ScatterObject, options:
 }
 
 
-fn draw_sched_switch(orig: f64, data: HashMap<u32, Vec<&Action>>, color_table: ColorTable, plot: &mut Plot, switch_markers: &mut ScatterObject, y_axis: &HashMap<u32, u32>, options: &Graph, marker_size: usize) {
-    let mut transparent_markers = ScatterObject::new(Mode::Markers, "switch", NamedColor::White);
+// duration of the segments drawn for each cpu, a segment being the time a task ran between two switches
+fn segment_durations(data: &HashMap<u32, Vec<&Action>>) -> HashMap<u32, Vec<f64>> {
+    let mut durations = HashMap::new();
+    for (core, switch_events) in data {
+        let core_durations: Vec<f64> = switch_events.windows(2)
+            .filter(|item| matches!(item[1].event, Events::SchedSwitch { old_pid, .. } if old_pid != 0))
+            .map(|item| item[1].timestamp - item[0].timestamp)
+            .filter(|duration| *duration >= 0.0)
+            .collect();
+        durations.insert(*core, core_durations);
+    }
+    durations
+}
+
+// Shortest segment kept on each cpu so that about max_segments are drawn in total
+// Every cpu keeps its share of max_segments, in proportion to its number of segments,
+// made of its longest segments, so busy cpus lose their slivers without emptying the others
+// Each cpu keeps at least its longest segment
+fn segment_thresholds(data: &HashMap<u32, Vec<&Action>>, max_segments: usize) -> HashMap<u32, f64> {
+    let durations = segment_durations(data);
+    let total: usize = durations.values().map(|core_durations| core_durations.len()).sum();
+    let mut thresholds = HashMap::new();
+    if total <= max_segments {
+        return thresholds;
+    }
+
+    let mut dropped = 0;
+    for (core, mut core_durations) in durations {
+        let keep = (core_durations.len() * max_segments / total).max(1);
+        core_durations.sort_by(|a, b| b.total_cmp(a));
+        let threshold = core_durations.get(keep - 1).copied().unwrap_or(f64::MAX);
+        dropped += core_durations.iter().filter(|duration| **duration < threshold).count();
+        thresholds.insert(core, threshold);
+    }
+    eprintln!("Warning: {} switch segments are over max_segments = {}, the {} shortest ones per cpu are not drawn, narrow custom_range to see them",
+                total, max_segments, dropped);
+    thresholds
+}
+
+// switch segments of a task on a cpu drawn as a single trace, with a gap between them
+struct SwitchLine {
+    task: (u32, String, Option<u32>),
+    group: String,
+    color: Rgb,
+    xs: Vec<Option<f64>>,
+    ys: Vec<Option<u32>>,
+    end: f64,
+}
+
+fn add_switch_line(plot: &mut Plot, line: SwitchLine, options: &Graph) {
+    let mut trace = Scatter::new(line.xs, line.ys)
+                        .mode(Mode::Lines)
+                        .hover_info(HoverInfo::Skip)
+                        .web_gl_mode(options.webgl)
+                        .show_legend(false);
+    if options.highlight_pid_on_hover {
+        trace = trace.legend_group(&line.group);
+    }
+    // overlapping translucent lines blend, so busy cpus show up darker
+    let width = if options.single_cpu.is_some() { LANE_WIDTH } else { options.line_width };
+    trace = trace.line(Line::new().color(line.color).width(width))
+                 .opacity(options.line_opacity);
+    plot.add_trace(trace);
+}
+
+fn draw_sched_switch(orig: f64, hover_orig: f64, data: HashMap<u32, Vec<&Action>>, color_table: ColorTable, plot: &mut Plot, switch_markers: &mut ScatterObject, y_axis: &HashMap<u32, u32>, options: &Graph, marker_size: usize, leaders: &HashMap<u32, u32>) -> usize {
+    // transparent markers are grouped per pid when highlighting a task across cpus
+    let mut transparent_markers: HashMap<String, ScatterObject> = HashMap::new();
+    // line_marker_count - 1 markers are drawn per segment, and none would be hovered on a static plot
+    let draw_transparent_markers = options.interactive && options.line_marker_count > 1;
+    let thresholds = options.max_segments.map(|max_segments| segment_thresholds(&data, max_segments)).unwrap_or_default();
+    let mut merged = 0;
     for (core, switch_events) in data {
+        let threshold = thresholds.get(&core).copied().unwrap_or(0.0);
+        let mut line: Option<SwitchLine> = None;
         for item in switch_events.windows(2) {
-            if let Events::SchedSwitch { old_command, old_pid, state, new_command, new_pid } = &item[1].event {
+            if let Events::SchedSwitch { old_command, old_pid, old_prio, state, new_command, new_pid, new_prio } = &item[1].event {
                 if *old_pid == 0 { continue; }
                 if !options.interactive && item[1].timestamp - item[0].timestamp < options.limit {
                     continue;
                 }
+                if item[1].timestamp - item[0].timestamp < threshold {
+                    continue;
+                }
+                // out of order events would otherwise draw a line backwards in time
+                if item[1].timestamp < item[0].timestamp {
+                    continue;
+                }
                 
-                let hover_text = format!("Timestamp: {}<br>From: {}<br>Pid: {}<br>State: {}<br>To: {}<br>Pid: {}",
-                                            item[1].timestamp, old_command, old_pid, state, new_command, new_pid);
-                
+                // the priorities are only in the hover text of traces that have them
+                let prio = |prio: &Option<u32>| prio.map(|prio| format!("<br>Prio: {}", prio)).unwrap_or_default();
+                let hover_text = format!("Timestamp: {:.*}<br>From: {}<br>Pid: {}{}<br>State: {}<br>To: {}<br>Pid: {}{}",
+                                            options.timestamp_precision, item[1].timestamp - hover_orig, old_command, old_pid, prio(old_prio), state, new_command, new_pid, prio(new_prio));
 
-                // draw the switch event lines
-                let mut trace = Scatter::new(vec![item[0].timestamp - orig, item[1].timestamp - orig], vec![y_axis[&core], y_axis[&core]])
-                                                            .mode(Mode::Lines)
-                                                            .hover_info(HoverInfo::Skip)   
-                                                            .web_gl_mode(options.webgl)
-                                                            .show_legend(false);
-                
+                // with group_by_tgid, the threads of a process highlight together
+                let group = if options.highlight_pid_on_hover { format!("pid {}", leaders.get(old_pid).unwrap_or(old_pid)) } else { String::from("switch") };
                 
+
                 let color = match &color_table {
                     ColorTable::Pid(colors) => colors[old_pid],
                     ColorTable::Command(colors) => colors[old_command],
-                    ColorTable::Parent(colors) => colors[old_pid]
+                    ColorTable::Parent(colors) => colors[old_pid],
+                    ColorTable::Cgroup(regex, colors) => colors[&cgroup_key(regex, old_command)],
+                    ColorTable::Prio => prio_color(*old_prio),
                 };
-                trace = trace.line(Line::new().color(color).width(1.0));
-                plot.add_trace(trace);
+
+                // draw the switch event lines, a segment close enough to the previous one of the same task joins its trace
+                let task = (*old_pid, old_command.clone(), *old_prio);
+                let (start, end) = (item[0].timestamp - orig, item[1].timestamp - orig);
+                match &mut line {
+                    Some(line) if line.task == task && start - line.end < options.merge_adjacent_below => {
+                        line.xs.extend([None, Some(start), Some(end)]);
+                        line.ys.extend([None, Some(y_axis[&core]), Some(y_axis[&core])]);
+                        line.end = end;
+                        merged += 1;
+                    }
+                    _ => {
+                        let next = SwitchLine { task, group: group.clone(), color, xs: vec![Some(start), Some(end)], ys: vec![Some(y_axis[&core]); 2], end };
+                        if let Some(line) = line.replace(next) {
+                            add_switch_line(plot, line, options);
+                        }
+                    }
+                }
 
                 // store the switch event notches in a scatterobject to draw together
                 switch_markers.xs.push(item[1].timestamp - orig);
@@ -266,7 +771,12 @@ fn draw_sched_switch(orig: f64, data: HashMap<u32, Vec<&Action>>, color_table: C
                 switch_markers.color_array.push(color);
 
                 // transparent markers: workaround for showing hover text on lines
+                if !draw_transparent_markers {
+                    continue;
+                }
                 let hover_text = format!("Command: {}<br>Pid: {}", old_command, old_pid);
+                let transparent_markers = transparent_markers.entry(group.clone())
+                                            .or_insert_with(|| ScatterObject::new(Mode::Markers, &group, Rgb::new(255, 255, 255)));
                 for i in 1..options.line_marker_count {
                     transparent_markers.xs.push(item[0].timestamp - orig + (item[1].timestamp - item[0].timestamp) / options.line_marker_count as f64 * i as f64);
                     transparent_markers.ys.push(y_axis[&core]);
@@ -275,36 +785,90 @@ fn draw_sched_switch(orig: f64, data: HashMap<u32, Vec<&Action>>, color_table: C
                 }
             }
         }
+        if let Some(line) = line {
+            add_switch_line(plot, line, options);
+        }
     }
     // draw the transparent markers
-    plot.add_trace(
-        Scatter::new(transparent_markers.xs, transparent_markers.ys)
-            .mode(Mode::Markers)
-            .marker(Marker::new().symbol(MarkerSymbol::LineNSOpen).color_array(transparent_markers.color_array).opacity(0.0).size(marker_size))
-            .hover_text_array(transparent_markers.hover_text)
-            .legend_group("switch")
-            .hover_info(HoverInfo::Text)
-            .show_legend(false)
-            .web_gl_mode(true)
-    )
+    for (_, markers) in transparent_markers {
+        plot.add_trace(
+            Scatter::new(markers.xs, markers.ys)
+                .mode(Mode::Markers)
+                .marker(Marker::new().symbol(MarkerSymbol::LineNSOpen).color_array(markers.color_array).opacity(0.0).size(marker_size))
+                .hover_text_array(markers.hover_text)
+                .legend_group(markers.name)
+                .hover_info(HoverInfo::Text)
+                .show_legend(false)
+                .web_gl_mode(true)
+        )
+    }
+    merged
 }   
 
-fn draw_migrate_marks(start_time: f64, action: &Action, traces: &mut Vec<Box<dyn Trace>>, legend_group: &str, color: NamedColor, y_axis: &HashMap<u32, u32>, webgl: bool, marker_size: usize) {
+// merge overlapping or touching intervals into their union
+fn merge_intervals(mut intervals: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut merged: Vec<(f64, f64)> = Vec::new();
+    for (start, end) in intervals {
+        if let Some(last) = merged.last_mut() {
+            if start <= last.1 {
+                last.1 = last.1.max(end);
+                continue;
+            }
+        }
+        merged.push((start, end));
+    }
+    merged
+}
+
+// socket view: draw the union of the busy intervals of all cpus in a socket on its row
+fn draw_socket_view(orig: f64, hover_orig: f64, data: HashMap<u32, Vec<&Action>>, plot: &mut Plot, y_axis: &HashMap<u32, u32>, options: &Graph) {
+    let mut busy: HashMap<u32, Vec<(f64, f64)>> = HashMap::new();
+    for (core, switch_events) in data {
+        for item in switch_events.windows(2) {
+            if let Events::SchedSwitch { old_pid, .. } = &item[1].event {
+                if *old_pid == 0 || item[1].timestamp < item[0].timestamp {
+                    continue;
+                }
+                busy.entry(y_axis[&core]).or_default().push((item[0].timestamp, item[1].timestamp));
+            }
+        }
+    }
+
+    for (socket, intervals) in busy {
+        for (start, end) in merge_intervals(intervals) {
+            let hover_text = format!("Socket: {}<br>Busy from: {:.*}<br>To: {:.*}", socket, options.timestamp_precision, start - hover_orig, options.timestamp_precision, end - hover_orig);
+            plot.add_trace(Scatter::new(vec![start - orig, end - orig], vec![socket, socket])
+                .mode(Mode::Lines)
+                .line(Line::new().color(NamedColor::SteelBlue).width(10.0))
+                .hover_text(hover_text)
+                .hover_info(HoverInfo::Text)
+                .web_gl_mode(options.webgl)
+                .show_legend(false));
+        }
+    }
+}
+
+// latency is the time since the waking of an unblock placement, added to the hover text
+fn draw_migrate_marks(start_time: f64, hover_orig: f64, action: &Action, latency: Option<f64>, traces: &mut Vec<Box<dyn Trace>>, legend_group: &str, color: impl Color + Copy, y_axis: &HashMap<u32, u32>, webgl: bool, precision: usize, marker_size: usize, line_width: f64) {
     if let Events::SchedMigrateTask { command, pid, orig_cpu, dest_cpu, .. } = &action.event {
 
         // draw the migrate event lines
         let trace = Scatter::new(
             vec![action.timestamp - start_time; 2], vec![Some(y_axis[orig_cpu]), Some(y_axis[dest_cpu])])
             .mode(Mode::Lines)
-            .line(Line::new().color(color).width(1.0))
+            .line(Line::new().color(color).width(line_width))
             .hover_info(HoverInfo::None)
             .legend_group(legend_group)
             .web_gl_mode(webgl)
             .show_legend(false);
         traces.push(trace);
 
-        let hover_text = format!("Timestamp: {}<br>Command: {}<br>Pid: {}<br>Src: {}<br>Dest: {}",
-                                    action.timestamp, command, pid, orig_cpu, dest_cpu);
+        let mut hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Pid: {}<br>Src: {}<br>Dest: {}",
+                                    precision, action.timestamp - hover_orig, command, pid, orig_cpu, dest_cpu);
+        if let Some(latency) = latency {
+            hover_text.push_str(&format!("<br>Since waking: {:.*}", precision, latency));
+        }
 
         // draw the migrate event notches
         // possible performance improvement by using a ScatterObject instead of drawing here
@@ -327,93 +891,250 @@ fn draw_migrate_marks(start_time: f64, action: &Action, traces: &mut Vec<Box<dyn
     }
 }
 
+// Arrow of a wakeup from the cpu of the waker to the cpu the wakee lands on, in the color of the wakeup event from colors
+// Drawn like a migration, with the head as a triangle on the wakee cpu, local wakeups have no arrow
+fn draw_wakeup_arrow(start_time: f64, hover_orig: f64, action: &Action, waker_cpu: u32, wakee_cpu: u32, traces: &mut Vec<Box<dyn Trace>>, legend_group: &str, y_axis: &HashMap<u32, u32>, options: &Graph, colors: &HashMap<String, Rgb>, marker_size: usize) {
+    let (Some(src), Some(dest)) = (y_axis.get(&waker_cpu), y_axis.get(&wakee_cpu)) else {
+        return;
+    };
+    if excluded(options, legend_group) {
+        return;
+    }
+    if src == dest {
+        return;
+    }
+    let color = colors[legend_group];
+    let x = action.timestamp - start_time;
+    let trace = Scatter::new(vec![x; 2], vec![*src, *dest])
+        .mode(Mode::Lines)
+        .line(Line::new().color(color).width(options.migrate_line_width))
+        .hover_info(HoverInfo::None)
+        .legend_group(legend_group)
+        .web_gl_mode(options.webgl)
+        .show_legend(false);
+    traces.push(trace);
+
+    let symbol = if src < dest { MarkerSymbol::TriangleUp } else { MarkerSymbol::TriangleDown };
+    let hover_text = format!("Timestamp: {:.*}<br>Waker: {}<br>Waker pid: {}<br>Src: {}<br>Dest: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, action.process, action.pid, waker_cpu, wakee_cpu);
+    let trace = Scatter::new(vec![x], vec![*dest])
+        .mode(Mode::Markers)
+        .marker(Marker::new().color(color).symbol(symbol).size(marker_size))
+        .name(legend_group)
+        .legend_group(legend_group)
+        .hover_text(hover_text)
+        .web_gl_mode(options.webgl)
+        .show_legend(false);
+    traces.push(trace);
+}
+
+// legend group of a migration from the src to the dest socket, by the waking state of the task
+fn migrate_category(state: Wstate, src: u32, dest: u32) -> &'static str {
+    match state {
+        Wstate::Waking(..) => {
+            if src == dest {
+                "on-socket<br>unblock placement"
+            }
+            else {
+                "off-socket<br>unblock placement"
+            }
+        },
+        Wstate::Woken => {
+            if src == dest {
+                "on-socket<br>load balancing"
+            }
+            else {
+                "off-socket<br>load balancing"
+            }
+        }
+        Wstate::Numa(..) => "numa balancing",
+    }
+}
 
 // Determine type of migrate event and draw
-fn classify_migrate_event(start_time: f64, action: &Action, states: &HashMap<u32, Wstate>, traces: &mut Vec<Box<dyn Trace>>, y_axis: &HashMap<u32, u32>, config: &Config, frequency: &mut HashMap<String, u32>, marker_size: usize) {
+fn classify_migrate_event(start_time: f64, hover_orig: f64, action: &Action, states: &HashMap<u32, Wstate>, traces: &mut Vec<Box<dyn Trace>>, y_axis: &HashMap<u32, u32>, config: &Config, frequency: &mut HashMap<String, u32>, colors: &HashMap<String, Rgb>, marker_size: usize) {
     if let Events::SchedMigrateTask { command: _, pid, orig_cpu, dest_cpu, state: _ } = &action.event {
-        let legend_group: &str;
-        let color: NamedColor;
         let (src, _) = get_socket_order(*orig_cpu, &config.machine);
         let (dest, _) = get_socket_order(*dest_cpu, &config.machine);
 
-        if states.contains_key(pid) {
-            match states[pid] {
-                Wstate::Waking(..) => {
-                    if src == dest {
-                        legend_group = "on-socket<br>unblock placement";
-                        color = NamedColor::DeepPink;
-                    } 
-                    else {
-                        legend_group = "off-socket<br>unblock placement";
-                        color = NamedColor::SkyBlue;
-                    }
-                },
-                Wstate::Woken => {
-                    if src == dest {
-                        legend_group = "on-socket<br>load balancing";
-                        color = NamedColor::Gold;
-                    }
-                    else {
-                        legend_group = "off-socket<br>load balancing";
-                        color = NamedColor::Orange;
+        // with one row per socket, a migration within the socket is not visible
+        if config.graph.view == "socket" && src == dest {
+            return;
+        }
+        if node_distance(src, dest, &config.machine) < config.graph.min_migration_distance {
+            return;
+        }
+
+        if let Some(state) = states.get(pid) {
+            let legend_group = migrate_category(*state, src, dest);
+            frequency.insert(legend_group.to_string(), frequency[legend_group] + 1);
+            // how long the unblock placement took, from the waking or fork of the task
+            let latency = match state {
+                Wstate::Waking(.., waking_time) => Some(action.timestamp - waking_time),
+                _ => None,
+            };
+
+            let options = &config.graph;
+            match options.migrate_color.as_str() {
+                "category" => {
+                    if excluded(options, legend_group) {
+                        return;
                     }
+                    draw_migrate_marks(start_time, hover_orig, action, latency, traces, legend_group, colors[legend_group], y_axis, options.webgl, options.timestamp_precision, marker_size, options.migrate_line_width);
                 }
-                Wstate::Numa(..) => {
-                    legend_group = "numa balancing";
-                    color = NamedColor::SeaGreen;
+                "distance" => {
+                    let hops = node_distance(src, dest, &config.machine);
+                    let max_hops = max_node_distance(&config.machine);
+                    let group = distance_group(hops);
+                    *frequency.entry(group.clone()).or_insert(0) += 1;
+                    if excluded(options, &group) {
+                        return;
+                    }
+                    draw_migrate_marks(start_time, hover_orig, action, latency, traces, &group, distance_color(hops, max_hops), y_axis, options.webgl, options.timestamp_precision, marker_size, options.migrate_line_width);
                 }
+                _ => { panic!("Invalid migrate color option"); }
             }
-            draw_migrate_marks(start_time, action, traces, legend_group, color, y_axis, config.graph.webgl, marker_size);
-            frequency.insert(legend_group.to_string(), frequency[legend_group] + 1);
         }
     }
 }
 
-fn draw_legends(plot: &mut Plot, frequency: HashMap<String, u32>, options: &Graph) {
-    let marker_legends = vec![("wakeup", NamedColor::RoyalBlue),
-                                            ("wakeup new", NamedColor::Brown),
-                                            ("wake idle no ipi", NamedColor::LimeGreen),
-                                            ("waking", NamedColor::DarkOliveGreen),
-                                            ("process fork", NamedColor::Pink)];
+// hops between two numa nodes, from node_hops, or assuming they are connected in a line
+// nodes outside of the machine config, like the one of hotplugged cpus, are also put at the end of the line
+fn node_distance(src: u32, dest: u32, machine: &Machine) -> u32 {
+    machine.node_hops.as_ref()
+        .and_then(|node_hops| node_hops.get(src as usize)?.get(dest as usize).copied())
+        .unwrap_or(src.abs_diff(dest))
+}
 
-    let migrate_legends = vec![
-                                    ("on-socket<br>unblock placement", NamedColor::DeepPink),
-                                    ("off-socket<br>unblock placement", NamedColor::SkyBlue),
-                                    ("numa balancing", NamedColor::SeaGreen),
-                                    ("on-socket<br>load balancing", NamedColor::Gold),
-                                    ("off-socket<br>load balancing", NamedColor::Orange)];
+// largest distance between two numa nodes of the machine
+fn max_node_distance(machine: &Machine) -> u32 {
+    match &machine.node_hops {
+        Some(node_hops) => node_hops.iter().flatten().copied().max().unwrap_or(0),
+        None => (machine.numa_node_ranges.len() as u32).saturating_sub(1),
+    }
+}
 
-    // marker legends: containing only a notch 
-    if options.events.show_events || options.events.show_marker_only {
-        for (legend_group, color) in marker_legends {
-            if frequency.contains_key(legend_group) {
-                let name = format!("{} ({})", legend_group, frequency[legend_group]);
-                plot.add_trace(Scatter::new(vec![0], vec![-1])
-                .mode(Mode::LinesMarkers)
-                .marker(Marker::new().color(color).symbol(MarkerSymbol::LineNSOpen))
-                .line(Line::new().width(1.0))
-                .legend_group(legend_group)
-                .hover_info(HoverInfo::Skip)
-                .name(&name));
-            }
+// legend group of the migrations over a distance
+fn distance_group(hops: u32) -> String {
+    format!("migrate {} hop{}", hops, if hops == 1 { "" } else { "s" })
+}
+
+// gradient from green for migrations within the node to red for the farthest nodes
+fn distance_color(hops: u32, max_hops: u32) -> Rgb {
+    let ratio = if max_hops == 0 { 0.0 } else { hops as f64 / max_hops as f64 };
+    Rgb::new((220.0 * ratio) as u8, (176.0 * (1.0 - ratio)) as u8, 60)
+}
+
+// Draw the numa balancer events on the cpu rows involved
+// A swap exchanges two tasks, so both cpus are drawn as a connected pair
+fn draw_numa_marks(start_time: f64, hover_orig: f64, action: &Action, traces: &mut Vec<Box<dyn Trace>>, y_axis: &HashMap<u32, u32>, symbols: &HashMap<String, MarkerSymbol>, colors: &HashMap<String, Rgb>, webgl: bool, precision: usize, marker_size: usize) {
+    let (legend_group, cpus, hover_text) = match &action.event {
+        Events::SchedSwapNuma { src_pid, src_cpu, dst_pid, dst_cpu } => {
+            ("numa swap", vec![*src_cpu, *dst_cpu],
+                format!("Timestamp: {:.*}<br>Src pid: {}<br>Src cpu: {}<br>Dst pid: {}<br>Dst cpu: {}",
+                        precision, action.timestamp - hover_orig, src_pid, src_cpu, dst_pid, dst_cpu))
+        },
+        Events::SchedStickNuma { src_pid, src_cpu, dst_pid, dst_cpu } => {
+            ("numa stick", vec![*src_cpu],
+                format!("Timestamp: {:.*}<br>Src pid: {}<br>Src cpu: {}<br>Dst pid: {}<br>Dst cpu: {}",
+                        precision, action.timestamp - hover_orig, src_pid, src_cpu, dst_pid, dst_cpu))
+        },
+        Events::SchedMoveNuma { src_pid, src_cpu, dst_cpu } => {
+            ("numa move", vec![*src_cpu],
+                format!("Timestamp: {:.*}<br>Pid: {}<br>Src cpu: {}<br>Dst cpu: {}",
+                        precision, action.timestamp - hover_orig, src_pid, src_cpu, dst_cpu))
+        },
+        _ => { return; }
+    };
+    let color = colors[legend_group];
+    let symbol = symbols[legend_group].clone();
+
+    // a cpu of -1 means the kernel had no cpu for that side of the event
+    let ys: Vec<u32> = cpus.iter()
+                            .filter(|cpu| **cpu >= 0)
+                            .map(|cpu| y_axis[&(*cpu as u32)])
+                            .collect();
+    if ys.is_empty() {
+        return;
+    }
+
+    let mode = if ys.len() > 1 { Mode::LinesMarkers } else { Mode::Markers };
+    let trace = Scatter::new(vec![action.timestamp - start_time; ys.len()], ys)
+        .mode(mode)
+        .line(Line::new().color(color).width(1.0))
+        .marker(Marker::new().color(color).symbol(symbol).size(marker_size))
+        .name(legend_group)
+        .legend_group(legend_group)
+        .hover_text(hover_text)
+        .web_gl_mode(webgl)
+        .show_legend(false);
+    traces.push(trace);
+}
+
+fn draw_legends(plot: &mut Plot, frequency: HashMap<String, u32>, options: &Graph, symbols: &HashMap<String, MarkerSymbol>, colors: &HashMap<String, Rgb>, max_hops: u32) {
+    let show_marker = options.events.show_events || options.events.show_marker_only;
+    let show_migrate = options.events.show_events || options.events.show_migrate;
+    let show_irq = options.events.show_events || options.events.show_irq;
+
+    // legend group, count and marker of every legend, added once sorted
+    let mut legends: Vec<(String, u32, Marker)> = Vec::new();
+    for (legend_group, _, category) in EVENTS {
+        let color = colors[legend_group];
+        let marker = match category {
+            // marker legends: containing only a notch
+            EventCategory::Marker(..) if show_marker => Marker::new().color(color).symbol(symbols[legend_group].clone()),
+            // migrate events: contain both lines and notches
+            EventCategory::Migrate if show_migrate && options.migrate_color == "category" => Marker::new().color(color).symbol(MarkerSymbol::TriangleRight)
+                                                        .line(Line::new().width(1.0).color(NamedColor::DarkSlateGrey)),
+            // numa balancer events: distinct symbols on the cpus involved
+            EventCategory::Numa(..) if show_migrate => Marker::new().color(color).symbol(symbols[legend_group].clone()),
+            // interrupts: ticks at the bottom of the cpu rows
+            EventCategory::Irq if show_irq => Marker::new().color(color).symbol(symbols[legend_group].clone()),
+            _ => continue,
+        };
+
+        if excluded(options, legend_group) {
+            continue;
         }
+        legends.push((legend_group.to_string(), frequency[legend_group], marker));
     }
 
-    // migrate events: contain both lines and notches
-    if options.events.show_events || options.events.show_migrate {
-        for (legend_group, color) in migrate_legends {
-            if frequency.contains_key(legend_group) {
-                let name = format!("{} ({})", legend_group, frequency[legend_group]);
-                plot.add_trace(Scatter::new(vec![0], vec![-1])
-                .mode(Mode::LinesMarkers)
-                .marker(Marker::new().color(color).symbol(MarkerSymbol::TriangleRight)
-                        .line(Line::new().width(1.0).color(NamedColor::DarkSlateGrey)))
-                .legend_group(&legend_group)
-                .hover_info(HoverInfo::Skip)
-                .name(name));
+    // migrate events colored by distance: one legend per hop count seen
+    if show_migrate && options.migrate_color == "distance" {
+        for hops in 0..=max_hops {
+            let legend_group = distance_group(hops);
+            if excluded(options, &legend_group) {
+                continue;
+            }
+            if let Some(count) = frequency.get(&legend_group) {
+                let marker = Marker::new().color(distance_color(hops, max_hops)).symbol(MarkerSymbol::TriangleRight)
+                                .line(Line::new().width(1.0).color(NamedColor::DarkSlateGrey));
+                legends.push((legend_group, *count, marker));
             }
         }
     }
+
+    // plotly lists the legends in the order of their traces
+    sort_legends(&mut legends, &options.legend_sort);
+    for (legend_group, count, marker) in legends {
+        plot.add_trace(Scatter::new(vec![0], vec![-1])
+        .mode(Mode::LinesMarkers)
+        .marker(marker)
+        .line(Line::new().width(1.0))
+        .legend_group(&legend_group)
+        .hover_info(HoverInfo::Skip)
+        .name(format!("{} ({})", legend_group, count)));
+    }
+}
+
+// order legends by legend_sort, fixed keeps the order of EVENTS, ties keep it as well
+fn sort_legends<T>(legends: &mut [(String, u32, T)], legend_sort: &str) {
+    match legend_sort {
+        "fixed" => {}
+        "frequency_desc" => legends.sort_by_key(|(_, count, _)| std::cmp::Reverse(*count)),
+        "name" => legends.sort_by(|a, b| a.0.cmp(&b.0)),
+        _ => { panic!("Invalid legend sort option, available options: fixed, frequency_desc, name"); }
+    }
 }
 
 
@@ -432,7 +1153,53 @@ fn draw_marker_event(plot: &mut Plot, marker_events: HashMap<String, ScatterObje
         let trace = Scatter::new(
             event.xs, event.ys)
             .mode(event.mode)
-            .marker(Marker::new().color(event.color).symbol(MarkerSymbol::LineNSOpen).size(marker_size))
+            .marker(Marker::new().color(event.color).symbol(event.symbol).size(marker_size))
+            .name(&event.name)
+            .legend_group(event.name)
+            .web_gl_mode(options.webgl)
+            .hover_text_array(event.hover_text)
+            .show_legend(false);
+        plot.add_trace(trace);
+    }
+}
+
+// whether the action is the start marker event of the start marker command
+fn is_start_marker(action: &Action, options: &Graph) -> bool {
+    let marker = options.start_marker_command.as_str();
+    match &action.event {
+        Events::SchedProcessExit { command, .. } => options.start_marker_event == "exit" && command == marker,
+        Events::SchedProcessFork { child_command, .. } => options.start_marker_event == "fork" && child_command == marker,
+        Events::SchedProcessExec { filename, .. } => options.start_marker_event == "exec" && filename.split("/").last() == Some(marker),
+        _ => false
+    }
+}
+
+// x values are relative to the start of the plot, unless absolute timestamps are asked for
+fn time_origin(options: &Graph, start_timestamp: f64) -> f64 {
+    if options.absolute_time {
+        0.0
+    } else {
+        start_timestamp
+    }
+}
+
+// timestamps of the hover text are the trace timestamps, unless hover_time is relative to the start of the plot
+// Independent of absolute_time, which only sets the x axis
+fn hover_origin(options: &Graph, start_timestamp: f64) -> f64 {
+    match options.hover_time.as_str() {
+        "absolute" => 0.0,
+        "relative" => start_timestamp,
+        _ => { panic!("Invalid hover time option"); }
+    }
+}
+
+// interrupts are drawn as ticks at the bottom of the cpu row, below the switch lines
+fn draw_irq_events(plot: &mut Plot, irq_events: HashMap<String, ScatterObject>, options: &Graph, marker_size: usize) {
+    for (_, event) in irq_events {
+        let ys: Vec<f64> = event.ys.iter().map(|y| *y as f64 - 0.3).collect();
+        let trace = Scatter::new(event.xs, ys)
+            .mode(event.mode)
+            .marker(Marker::new().color(event.color).symbol(event.symbol).size(marker_size / 2 + 1))
             .name(&event.name)
             .legend_group(event.name)
             .web_gl_mode(options.webgl)
@@ -442,183 +1209,982 @@ fn draw_marker_event(plot: &mut Plot, marker_events: HashMap<String, ScatterObje
     }
 }
 
-// find the first sleep command's exit point
+// find the first start marker event, by default the exit of the first sleep command
 // It then becomes the starting point of the plot
-fn find_sleep(reader: &mut TraceParser, options: &Graph) {
+// If there is no marker in the trace, the plot starts at the first timestamp
+fn find_sleep(reader: &mut TraceParser, filepath: &str, options: &Graph) {
     if options.sleep {
+        if !["exit", "fork", "exec"].contains(&options.start_marker_event.as_str()) {
+            panic!("Invalid start marker event");
+        }
         while let Some((action, ..)) = reader.next_action() {
-            if let Events::SchedProcessExit { command, .. } = &action.event {
-                if command == "sleep" {
-                    reader.first_timestamp = Some(action.timestamp);
-                    break;
-                }
+            if is_start_marker(&action, options) {
+                // the events read before the marker are not plotted
+                reader.start_timestamp = Some(action.timestamp);
+                reader.first_timestamp = Some(action.timestamp);
+                reader.last_timestamp = Some(action.timestamp);
+                return;
             }
         }
+
+        // the whole trace was read looking for the marker, start again from the beginning
+        let strict_order = reader.strict_order;
+        let task_states = std::mem::take(&mut reader.task_states);
+        *reader = TraceParser::new(filepath);
+        reader.strict_order = strict_order;
+        reader.task_states = task_states;
     }
 }
 
-fn draw_traces(filepath: &str, config: &Config, plot: &mut Plot) -> TraceParser {
-    let mut reader = TraceParser::new(filepath);
-    let mut switch_events: Vec<Action> = Vec::new();
-    let mut boundary_events: HashMap<u32, Action> =  HashMap::new();
-    let mut fork_events: Vec<Action> = Vec::new();
-    let mut migrate_traces: Vec<Box<dyn Trace>> = Vec::new();
-    let mut marker_events = marker_events_object();
-    let mut frequency: HashMap<String, u32> = get_frequency_map();
-
-    let options = &config.graph;
-    let y_axis = get_y_axis(&config.machine, options.socket_order, reader.cpu_count);
-    let marker_size = set_marker_size(reader.cpu_count);
+// class of the task state strings of the config, which override the first letter classification
+fn task_states(options: &Graph) -> HashMap<String, TaskState> {
+    options.task_states.iter().map(|(state, name)| {
+        let class = parse_task_state(name).unwrap_or_else(|| {
+            let names: Vec<&str> = TASK_STATES.iter().map(|(name, _)| *name).collect();
+            panic!("Unknown class {} for task state {}, available classes: {}", name, state, names.join(", "))
+        });
+        (state.clone(), class)
+    }).collect()
+}
 
-    find_sleep(&mut reader, options);
+// cpus switching to or from a task other than idle, from a first pass over the plotted part of the trace
+// Limited to the cpu filter if there is one
+fn busy_cpus(filepath: &str, options: &Graph) -> Vec<u32> {
+    let mut reader = TraceParser::new(filepath);
+    find_sleep(&mut reader, filepath, options);
+
+    let mut busy = Vec::new();
+    while let Some((action, ..)) = reader.next_action() {
+        if let Events::SchedSwitch { old_pid, new_pid, .. } = action.event {
+            if (old_pid != 0 || new_pid != 0) && !busy.contains(&action.cpu)
+                && (options.cpu_filter.is_empty() || options.cpu_filter.contains(&action.cpu)) {
+                busy.push(action.cpu);
+            }
+        }
+    }
+    busy
+}
+
+// set by ctrl-c while a trace is parsed for a plot, which stops the parsing and plots the events read so far
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// whether a trace is being parsed for a plot, ctrl-c exits right away otherwise
+static PARSING: AtomicBool = AtomicBool::new(false);
+
+// Handler of ctrl-c: the first one while parsing a trace for a plot stops the parsing, any other one exits
+pub fn interrupt() {
+    if PARSING.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst) {
+        eprintln!("Interrupted, plotting the events read so far, ctrl-c again to quit");
+    } else {
+        exit(130);
+    }
+}
+
+// whether the last plot was cut short by ctrl-c, the next files are not plotted
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// whether a trace had more lines skipped than fail_on_skip allows, the process then exits with code 2
+static TOO_MANY_SKIPPED: AtomicBool = AtomicBool::new(false);
+
+// whether skipped lines out of lines_read are past the fail_on_skip threshold,
+// a fraction of the lines read when below 1, otherwise a number of lines
+fn skipped_past(skipped: u64, lines_read: u64, threshold: f64) -> bool {
+    let limit = if threshold < 1.0 { threshold * lines_read as f64 } else { threshold };
+    skipped as f64 > limit
+}
+
+// Check the unsupported and malformed lines of a parsed trace against fail_on_skip
+pub(crate) fn check_skipped(filename: &str, reader: &TraceParser, options: &Graph) {
+    let Some(threshold) = options.fail_on_skip else {
+        return;
+    };
+    let skipped = reader.unsupported + reader.malformed;
+    if skipped_past(skipped, reader.lines_read, threshold) {
+        eprintln!("Error: {} of the {} lines of {} were skipped, more than fail_on_skip {}", skipped, reader.lines_read, filename, threshold);
+        TOO_MANY_SKIPPED.store(true, Ordering::SeqCst);
+    }
+}
+
+// whether a trace failed the fail_on_skip check
+pub fn too_many_skipped() -> bool {
+    TOO_MANY_SKIPPED.load(Ordering::SeqCst)
+}
+
+// returns the parser, the rows of the cpus, the number of duplicate switch events dropped and of switch segments merged
+fn draw_traces(filepath: &str, config: &Config, plot: &mut Plot, lifetime_plot: &mut Plot) -> Option<(DrawnFile, usize, usize)> {
+    let header_start = Instant::now();
+    let mut reader = TraceParser::new(filepath);
+    benchmark::record("header parse", header_start.elapsed());
+    reader.strict_order = config.graph.strict_order;
+    let state_classes = task_states(&config.graph);
+    reader.task_states = state_classes.clone();
+    let mut switch_events: Vec<Action> = Vec::new();
+    let mut switched_in: HashMap<u32, Action> = HashMap::new();
+    let mut switched_out: HashMap<u32, Action> = HashMap::new();
+    let mut switched_cpus: HashSet<u32> = HashSet::new();
+    let mut fork_events: Vec<Action> = Vec::new();
+    let mut exec_pids: HashSet<u32> = HashSet::new();
+    let mut forked_at: HashMap<u32, (f64, u32)> = HashMap::new();
+    let mut lifetimes = Lifetimes::default();
+    let mut migrate_traces: Vec<Box<dyn Trace>> = Vec::new();
+    let mut wakeup_arrows: Vec<Box<dyn Trace>> = Vec::new();
+    let symbols = event_symbols(&config.graph);
+    let colors = event_colors(&config.graph);
+    check_exclude_events(&config.graph);
+    let mut marker_events = marker_events_object(&symbols, &colors);
+    let mut irq_events = irq_events_object(&symbols, &colors);
+    let mut frequency: HashMap<String, u32> = get_frequency_map();
+    let mut runqueue_depth: Vec<(f64, u32)> = Vec::new();
+    // events of every activity_bucket window, by window index from the origin
+    let mut activity: HashMap<i64, u32> = HashMap::new();
+    // idle period in progress on every cpu, with its c-state, and the finished ones as (cpu, start, end, c-state)
+    let mut idle_since: HashMap<u32, (f64, u32)> = HashMap::new();
+    let mut idle_bands: Vec<(u32, f64, f64, u32)> = Vec::new();
+
+    let options = &config.graph;
+    let mut cpu_filter = options.cpu_filter.clone();
+    if options.hide_idle_cpus {
+        cpu_filter = busy_cpus(filepath, options);
+        if cpu_filter.is_empty() {
+            eprintln!("Warning: all cpus are idle, none of them is hidden");
+        }
+    }
+    // a single lane replaces any other filter
+    if let Some(cpu) = options.single_cpu {
+        if cpu >= reader.cpu_count {
+            panic!("single_cpu {} is not in the trace, which has {} cpus", cpu, reader.cpu_count);
+        }
+        cpu_filter = vec![cpu];
+    }
+    let mut y_axis = match options.view.as_str() {
+        "cpu" => get_y_axis(&config.machine, options.socket_order, reader.cpu_count, &cpu_filter, &options.cpu_order),
+        "socket" => get_socket_axis(&config.machine, reader.cpu_count, &cpu_filter),
+        _ => { panic!("Invalid view option"); }
+    };
+    let marker_size = set_marker_size(reader.cpu_count);
+    let cpu_count = reader.cpu_count;
+
+    // the events are parsed and collected for the plot in the same pass
+    let parse_start = Instant::now();
+    find_sleep(&mut reader, filepath, options);
+    // jump near min instead of parsing the whole trace before it
+    if options.custom_range && options.seek_to_min && options.min > 0.0 {
+        if let Some(start_timestamp) = reader.read_start_timestamp() {
+            reader.seek_to_timestamp(time_origin(options, start_timestamp) + options.min);
+        }
+    }
+
+    // a ctrl-c only cuts the plot it happened in, like the previous plot of a watched file
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    PARSING.store(true, Ordering::SeqCst);
+    while let Some((action, states, Some(start_timestamp))) = reader.next_action() {
+        let start_time = time_origin(options, start_timestamp);
+        let hover_orig = hover_origin(options, start_timestamp);
+        if !cpu_filter.is_empty() && !on_displayed_cpus(&action, &y_axis) {
+            continue;
+        }
+        if cpu_filter.is_empty() {
+            for cpu in action_cpus(&action) {
+                if add_unexpected_cpu(&mut y_axis, cpu, &config.machine, &options.view) {
+                    eprintln!("Warning: cpu {} is not in the {} cpus of the trace header, it is drawn on a row of its own", cpu, cpu_count);
+                }
+            }
+        }
 
-    while let Some((action, states, Some(start_time))) = reader.next_action() {
         // collect the switch events going through the boundary of the range
+        // The last switch before min starts the line of its cpu, cpus without one start at their first switch in the range
         if options.custom_range {
             if action.timestamp - start_time < options.min {
                 if let Events::SchedSwitch { .. } = action.event {
-                    boundary_events.insert(action.cpu, action);
+                    switched_in.insert(action.cpu, action);
                 }
                 continue;
             }
-            else if action.timestamp - start_time > options.max {
-                if boundary_events.len() < reader.cpu_count.try_into().unwrap() {
-                    if let Events::SchedSwitch { .. } = action.event {
-                        if let None = boundary_events.get(&action.cpu) {
-                            boundary_events.insert(action.cpu, action);
-                        }
+            for (cpu, v) in switched_in.drain() {
+                switched_cpus.insert(cpu);
+                switch_events.push(v);
+            }
+            // the first switch after max ends the line of its cpu, cpus without a switch up to max have no line to end
+            if action.timestamp - start_time > options.max {
+                if let Events::SchedSwitch { .. } = action.event {
+                    if switched_cpus.contains(&action.cpu) {
+                        switched_out.entry(action.cpu).or_insert(action);
                     }
+                }
+                if switched_out.len() < switched_cpus.len() {
                     continue;
-                } else {
-                    for (_, v) in boundary_events.drain() {
-                        switch_events.push(v);
-                    }
-                    break;
                 }
+                break;
             }
         }
-        
+
+        if options.lifetime_view {
+            lifetimes.record(&action);
+        }
+
+        // the plot ends at the exit of the end marker command
+        let end_reached = match (&action.event, &options.end_marker_command) {
+            (Events::SchedProcessExit { command, .. }, Some(marker)) => command == marker,
+            _ => false
+        };
+
+        let timestamp = action.timestamp;
+
         // match and store the events
         let mut name = "";
         match &action.event {
-            Events::SchedSwitch { .. } => {
+            Events::SchedSwitch { old_command, old_pid, state, .. } => {
                 name = "switch";
-                if options.custom_range && !boundary_events.is_empty()  {
-                    for (_, v) in boundary_events.drain() {
-                        switch_events.push(v);
-                    }
+                // uninterruptible sleep, usually waiting on I/O or a lock
+                if classify_state(state, &state_classes) == TaskState::Uninterruptible {
+                    let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Pid: {}<br>State: {}",
+                                    options.timestamp_precision, action.timestamp - hover_orig, old_command, old_pid, state);
+                    add_event(&mut marker_events, &action, start_time, &y_axis, "enter D-state", hover_text);
+                    frequency.insert(String::from("enter D-state"), frequency["enter D-state"] + 1);
                 }
+                switched_cpus.insert(action.cpu);
                 switch_events.push(action);
             },
-            Events::SchedWakeup { command, pid, .. } => {
+            Events::SchedWakeup { command, pid, prev_cpu, cpu } => {
                 name = "wakeup";
-                let hover_text = format!("Timestamp: {}<br>Waker: {}<br>Waker pid: {}<br>Wakee: {}<br>Wakee pid: {}",
-                                action.timestamp, action.process, action.pid, command, pid);
+                let hover_text = format!("Timestamp: {:.*}<br>Waker: {}<br>Waker pid: {}<br>Wakee: {}<br>Wakee pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, action.process, action.pid, command, pid);
                 add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
-
+                // the waker cpu is the one of the waking, when it is in the trace
+                if let (true, Some(prev_cpu)) = (options.wakeup_arrows, prev_cpu) {
+                    draw_wakeup_arrow(start_time, hover_orig, &action, *prev_cpu, *cpu, &mut wakeup_arrows, name, &y_axis, options, &colors, marker_size);
+                }
             },
-            Events::SchedWakeupNew { command: _, pid, parent_cpu: _, cpu } => {
+            Events::SchedWakeupNew { command: _, pid, parent_cpu: _, cpu, .. } => {
                 name = "wakeup new";
-                let hover_text = format!("Timestamp: {}<br>Command: {}<br>Waker pid: {}<br>Wakee pid: {}<br>Target cpu: {}",
-                                action.timestamp, action.process, action.pid, pid, cpu);
+                let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Waker pid: {}<br>Wakee pid: {}<br>Target cpu: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, action.process, action.pid, pid, cpu);
                 add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
             },
             Events::SchedWakeIdleNoIpi { .. } => {
                 name = "wake idle no ipi";
-                let hover_text = format!("Timestamp: {}<br>Command: {}<br>Pid: {}", action.timestamp, action.process, action.pid);
+                let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Pid: {}", options.timestamp_precision, action.timestamp - hover_orig, action.process, action.pid);
                 add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
             }
             Events::SchedWaking { command: _, pid, target_cpu } => {
                 name = "waking";
-                let hover_text = format!("Timestamp: {}<br>Command: {}<br>Waker pid: {}<br>Wakee pid: {}<br>Target cpu: {}",
-                                action.timestamp, action.process, action.pid, pid, target_cpu);
+                let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Waker pid: {}<br>Wakee pid: {}<br>Target cpu: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, action.process, action.pid, pid, target_cpu);
                 add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
+                if options.wakeup_arrows {
+                    draw_wakeup_arrow(start_time, hover_orig, &action, action.cpu, *target_cpu, &mut wakeup_arrows, name, &y_axis, options, &colors, marker_size);
+                }
             },
             Events::SchedProcessFork { command, pid, child_command, child_pid } => {
                 name = "process fork";
-                let hover_text = format!("Timestamp: {}<br>Command: {}<br>Pid: {}<br>Child command: {}<br>Child pid: {}",
-                                action.timestamp, command, pid, child_command, child_pid);
+                let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Pid: {}<br>Child command: {}<br>Child pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, command, pid, child_command, child_pid);
                 add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
+                forked_at.insert(*child_pid, (action.timestamp, *pid));
                 fork_events.push(action);
             },
+            Events::SchedProcessExec { filename, pid, old_pid } => {
+                name = "process exec";
+                let mut hover_text = format!("Timestamp: {:.*}<br>Filename: {}<br>Pid: {}<br>Old pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, filename, pid, old_pid);
+                // link the exec to the fork that created the task, if it is in the trace
+                if let Some((timestamp, parent)) = forked_at.get(old_pid) {
+                    hover_text.push_str(&format!("<br>Forked at: {:.*}<br>Parent pid: {}", options.timestamp_precision, timestamp - hover_orig, parent));
+                }
+                add_event(&mut marker_events, &action, start_time, &y_axis, name, hover_text);
+                exec_pids.insert(*pid);
+            },
+            Events::SchedPiSetprio { command, pid, old_prio, new_prio } => {
+                name = "pi setprio";
+                let hover_text = format!("Timestamp: {:.*}<br>Command: {}<br>Pid: {}<br>Prio: {} -> {}<br>Set by: {}<br>Set by pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, command, pid, old_prio, new_prio, action.process, action.pid);
+                // on the cpu of the boosted task, the waiter that boosts it runs on another cpu
+                let cpu = reader.task_cpu(*pid).unwrap_or(action.cpu);
+                if let (Some(entry), Some(row)) = (marker_events.get_mut(name), y_axis.get(&cpu)) {
+                    entry.xs.push(action.timestamp - start_time);
+                    entry.ys.push(*row);
+                    entry.hover_text.push(hover_text);
+                }
+            }
+            Events::IrqHandlerEntry { irq, name: handler, .. } => {
+                name = "irq";
+                let hover_text = format!("Timestamp: {:.*}<br>Irq: {}<br>Handler: {}<br>Interrupted: {}<br>Pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, irq, handler, action.process, action.pid);
+                add_event(&mut irq_events, &action, start_time, &y_axis, name, hover_text);
+            }
+            Events::SoftirqEntry { vec, action: softirq_action, .. } => {
+                name = "softirq";
+                let hover_text = format!("Timestamp: {:.*}<br>Vec: {}<br>Action: {}<br>Interrupted: {}<br>Pid: {}",
+                                options.timestamp_precision, action.timestamp - hover_orig, vec, softirq_action, action.process, action.pid);
+                add_event(&mut irq_events, &action, start_time, &y_axis, name, hover_text);
+            }
+            Events::CpuIdle { state, cpu } => {
+                name = "cpu idle";
+                // entering another c-state ends the current band
+                if let Some((since, previous)) = idle_since.remove(cpu) {
+                    idle_bands.push((*cpu, since, action.timestamp, previous));
+                }
+                if *state != IDLE_EXIT {
+                    idle_since.insert(*cpu, (action.timestamp, *state));
+                }
+            }
             Events::SchedMigrateTask { .. } => {
                 name = "migrate task";
-                classify_migrate_event(start_time, &action, states, &mut migrate_traces, &y_axis, config, &mut frequency, marker_size);
+                classify_migrate_event(start_time, hover_orig, &action, states, &mut migrate_traces, &y_axis, config, &mut frequency, &colors, marker_size);
+            }
+            Events::SchedSwapNuma { .. } => {
+                name = "numa swap";
+                if !excluded(options, name) {
+                    draw_numa_marks(start_time, hover_orig, &action, &mut migrate_traces, &y_axis, &symbols, &colors, options.webgl, options.timestamp_precision, marker_size);
+                }
+            }
+            Events::SchedStickNuma { .. } => {
+                name = "numa stick";
+                if !excluded(options, name) {
+                    draw_numa_marks(start_time, hover_orig, &action, &mut migrate_traces, &y_axis, &symbols, &colors, options.webgl, options.timestamp_precision, marker_size);
+                }
+            }
+            Events::SchedMoveNuma { .. } => {
+                name = "numa move";
+                if !excluded(options, name) {
+                    draw_numa_marks(start_time, hover_orig, &action, &mut migrate_traces, &y_axis, &symbols, &colors, options.webgl, options.timestamp_precision, marker_size);
+                }
             }
             _ => { }
         }
         if frequency.contains_key(name) {
             frequency.insert(name.to_string(), frequency[name] + 1);
         }
+
+        // runnable tasks over all displayed cpus, only kept when it changes
+        if options.runqueue_depth {
+            let depth = y_axis.keys().map(|cpu| reader.runqueue_depth(*cpu)).sum();
+            if runqueue_depth.last().is_none_or(|(_, last)| *last != depth) {
+                runqueue_depth.push((timestamp, depth));
+            }
+        }
+        if options.activity_strip {
+            *activity.entry(((timestamp - start_time) / options.activity_bucket).floor() as i64).or_insert(0) += 1;
+        }
+
+        if end_reached || INTERRUPTED.load(Ordering::Relaxed) {
+            break;
+        }
     }
+    PARSING.store(false, Ordering::SeqCst);
+    benchmark::record("event parse", parse_start.elapsed());
+    // nothing to draw, like a trace still being written that only has its header
+    reader.start_timestamp?;
+    // also reached at the end of the file, with the cpus that switched after max
+    switch_events.extend(switched_out.into_values());
 
+    let color_start = Instant::now();
+    let reserved = reserved_colors(options);
     let color_table = match options.color_by.as_str() {
-        "pid" => color_by_pid(&switch_events),
-        "command" => color_by_command(&switch_events),
-        "parent" => color_by_parent(&fork_events),
+        "pid" => color_by_pid(&switch_events, options.max_colors, &reserved),
+        "command" => color_by_command(&switch_events, options.max_colors, &reserved),
+        "parent" => {
+            // tasks without a fork in the trace get their own color
+            let mut color_table = color_by_parent(&fork_events, &reserved);
+            if let (ColorTable::Parent(colors), ColorTable::Pid(pid_colors)) = (&mut color_table, color_by_pid(&switch_events, options.max_colors, &reserved)) {
+                for (pid, color) in pid_colors {
+                    colors.entry(pid).or_insert(color);
+                }
+            }
+            color_table
+        },
+        "cgroup" => {
+            let regex = options.cgroup_regex.as_deref().unwrap_or_else(|| panic!("color_by cgroup needs a cgroup_regex"));
+            color_by_cgroup(&switch_events, Regex::new(regex).unwrap_or_else(|err| panic!("Invalid cgroup_regex {}: {}", regex, err)), options.max_colors, &reserved)
+        },
+        "prio" => ColorTable::Prio,
         _ => { panic!("Invalid color option"); }
     };
+    let leaders = if options.group_by_tgid { thread_groups(&fork_events, &exec_pids) } else { HashMap::new() };
+    let color_table = group_colors(color_table, &leaders);
+    benchmark::record("color table", color_start.elapsed());
 
     // group and draw switch events
-    let switch_events = get_sched_switch_events(&switch_events);
-    let mut switch_markers = ScatterObject::new(Mode::LinesMarkers, "switch", NamedColor::White);
-    draw_sched_switch(reader.first_timestamp.unwrap(), switch_events, color_table, plot, &mut switch_markers, &y_axis, options, marker_size);
-    draw_switch_markers(plot, switch_markers, options, marker_size);
+    let orig = time_origin(options, reader.start_timestamp.unwrap());
+    let hover_orig = hover_origin(options, reader.start_timestamp.unwrap());
+    let (switch_events, duplicate_switches) = get_sched_switch_events(&switch_events);
+    if options.occupancy_chart {
+        let filename = filepath.split("/").last().unwrap();
+        occupancy::occupancy_chart(&switch_events, reader.last_timestamp.unwrap(), options, filename);
+    }
+    if options.show_switch_rate {
+        draw_switch_rate(orig, plot, &switch_events, &y_axis, options);
+    }
+    let utilization = if options.show_utilization {
+        let (window, _) = duration_range(options, &reader);
+        cpu_utilization(&switch_events, &window, orig, reader.last_timestamp.unwrap())
+    } else {
+        HashMap::new()
+    };
+    // drawn first so the task lines stay on top
+    if (options.events.show_events || options.events.show_cpu_idle) && options.view == "cpu" && !excluded(options, "cpu idle") {
+        let end = reader.last_timestamp.unwrap_or(orig);
+        idle_bands.extend(idle_since.into_iter().map(|(cpu, (since, state))| (cpu, since, end, state)));
+        draw_cpu_idle(orig, hover_orig, plot, idle_bands, &y_axis, options);
+    }
+    if let Some(cpu) = options.single_cpu {
+        let (range, _) = duration_range(options, &reader);
+        draw_segment_labels(orig, switch_events.get(&cpu).map_or(&[][..], Vec::as_slice), plot, &y_axis, options, range[1] - range[0]);
+    }
+    let mut merged_segments = 0;
+    if options.view == "socket" {
+        draw_socket_view(orig, hover_orig, switch_events, plot, &y_axis, options);
+    } else {
+        let mut switch_markers = ScatterObject::new(Mode::LinesMarkers, "switch", Rgb::new(255, 255, 255));
+        merged_segments = draw_sched_switch(orig, hover_orig, switch_events, color_table, plot, &mut switch_markers, &y_axis, options, marker_size, &leaders);
+        draw_switch_markers(plot, switch_markers, options, marker_size);
+    }
 
+    // the excluded events are still counted, only their markers and legends are left out
+    marker_events.retain(|name, _| !excluded(options, name));
+    irq_events.retain(|name, _| !excluded(options, name));
     if options.events.show_events || options.events.show_marker_only {
+        plot.add_traces(wakeup_arrows);
         draw_marker_event(plot, marker_events, options, marker_size);
     }
     if options.events.show_events || options.events.show_migrate {
         plot.add_traces(migrate_traces);
     }
-    draw_legends(plot, frequency, options);
-    reader
+    if options.events.show_events || options.events.show_irq {
+        draw_irq_events(plot, irq_events, options, marker_size);
+    }
+    if options.runqueue_depth {
+        draw_runqueue_depth(orig, hover_orig, plot, runqueue_depth, options);
+    }
+    if options.activity_strip {
+        draw_activity_strip(plot, activity, options);
+    }
+    draw_legends(plot, frequency, options, &symbols, &colors, max_node_distance(&config.machine));
+
+    if options.lifetime_view {
+        let filename = filepath.split("/").last().unwrap();
+        draw_lifetimes(lifetime_plot, &lifetimes, &fork_events, reader.first_timestamp.unwrap(), reader.last_timestamp.unwrap(), options, filename);
+    }
+    Some(((reader, y_axis, utilization), duplicate_switches, merged_segments))
 }
 
-pub fn data_graph(filepath: &str, config: &Config) {
+// Line below the first row of every numa node but the first, and the node name above it, at the left of the plot
+// Nothing is drawn if the cpus of a node are not on consecutive rows, as there is no boundary to show
+pub(crate) fn node_separators(y_axis: &HashMap<u32, u32>, machine: &Machine, y_ref: &str) -> (Vec<Shape>, Vec<Annotation>) {
+    let mut nodes: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (cpu, row) in y_axis {
+        nodes.entry(get_socket_order(*cpu, machine).0).or_default().push(*row);
+    }
+    let mut blocks: Vec<(u32, u32)> = Vec::new();
+    for (node, rows) in nodes {
+        let (first, last) = (*rows.iter().min().unwrap(), *rows.iter().max().unwrap());
+        if last - first + 1 != rows.len() as u32 {
+            eprintln!("Warning: the cpus of node {} are not on consecutive rows, node_separators needs socket_order", node);
+            return (Vec::new(), Vec::new());
+        }
+        blocks.push((first, node));
+    }
+    blocks.sort();
+
+    let shapes = blocks.iter().skip(1).map(|(first, _)| Shape::new()
+                                            .shape_type(ShapeType::Line)
+                                            .x_ref("paper")
+                                            .y_ref(y_ref)
+                                            .x0(0)
+                                            .x1(1)
+                                            .y0(*first as f64 - 0.5)
+                                            .y1(*first as f64 - 0.5)
+                                            .line(ShapeLine::new().color(NamedColor::LightGray).width(1.0).dash(DashType::Dot))).collect();
+    let annotations = blocks.iter().map(|(first, node)| Annotation::new()
+                                            .text(format!("node{}", node))
+                                            .x_ref("paper")
+                                            .y_ref(y_ref)
+                                            .x(0.0)
+                                            .y(*first as f64 - 0.5)
+                                            .x_anchor(Anchor::Left)
+                                            .y_anchor(Anchor::Bottom)
+                                            .font(Font::new().size(10).color(NamedColor::DimGray))
+                                            .show_arrow(false)).collect();
+    (shapes, annotations)
+}
+
+// Busy fraction of every cpu over the window, from the time it runs tasks other than idle
+// A task runs from its switch in to the next switch on the cpu, or to last_timestamp, clipped to the window
+fn cpu_utilization(switch_events: &HashMap<u32, Vec<&Action>>, window: &[f64], orig: f64, last_timestamp: f64) -> HashMap<u32, f64> {
+    let length = window[1] - window[0];
+    switch_events.iter().map(|(cpu, events)| {
+        let ends = events.iter().skip(1).map(|action| action.timestamp).chain(std::iter::once(last_timestamp));
+        let busy: f64 = events.iter().zip(ends).filter_map(|(action, end)| match &action.event {
+            Events::SchedSwitch { new_pid, .. } if *new_pid != 0 => {
+                Some(((end - orig).min(window[1]) - (action.timestamp - orig).max(window[0])).max(0.0))
+            }
+            _ => None,
+        }).sum();
+        (*cpu, if length > 0.0 { busy / length } else { 0.0 })
+    }).collect()
+}
+
+// busy percentage at the right of every row of y_ref, averaged over the cpus of a socket in the socket view
+pub(crate) fn utilization_annotations(utilization: &HashMap<u32, f64>, y_axis: &HashMap<u32, u32>, y_ref: &str) -> Vec<Annotation> {
+    let mut rows: HashMap<u32, Vec<f64>> = HashMap::new();
+    for (cpu, row) in y_axis {
+        rows.entry(*row).or_default().push(utilization.get(cpu).copied().unwrap_or(0.0));
+    }
+    rows.into_iter().map(|(row, busy)| {
+        let average = busy.iter().sum::<f64>() / busy.len() as f64;
+        Annotation::new()
+            .text(format!("{:.1}%", 100.0 * average))
+            .x_ref("paper")
+            .y_ref(y_ref)
+            .x(1.0)
+            .y(row)
+            .x_anchor(Anchor::Left)
+            .show_arrow(false)
+    }).collect()
+}
+
+// line width of the segments of a single cpu lane, which fills the plot height
+const LANE_WIDTH: f64 = 60.0;
+
+// command names printed on the segments of a single cpu lane, for the ones wide enough to hold them
+// A segment needs LABEL_MIN_FRACTION of the plotted duration, shorter ones only have their hover text
+const LABEL_MIN_FRACTION: f64 = 0.02;
+
+fn draw_segment_labels(orig: f64, switch_events: &[&Action], plot: &mut Plot, y_axis: &HashMap<u32, u32>, options: &Graph, duration: f64) {
+    let (mut xs, mut ys, mut labels) = (Vec::new(), Vec::new(), Vec::new());
+    for item in switch_events.windows(2) {
+        if let Events::SchedSwitch { old_command, old_pid, .. } = &item[1].event {
+            if *old_pid != 0 && item[1].timestamp - item[0].timestamp >= duration * LABEL_MIN_FRACTION {
+                xs.push((item[0].timestamp + item[1].timestamp) / 2.0 - orig);
+                ys.push(y_axis[&item[1].cpu]);
+                labels.push(old_command.clone());
+            }
+        }
+    }
+    plot.add_trace(Scatter::new(xs, ys)
+        .mode(Mode::Text)
+        .text_array(labels)
+        .hover_info(HoverInfo::Skip)
+        .web_gl_mode(options.webgl)
+        .show_legend(false));
+}
+
+// idle bands on the cpu rows, one legend entry per c-state, darker for deeper states
+fn draw_cpu_idle(orig: f64, hover_orig: f64, plot: &mut Plot, idle_bands: Vec<(u32, f64, f64, u32)>, y_axis: &HashMap<u32, u32>, options: &Graph) {
+    let deepest = idle_bands.iter().map(|(.., state)| *state).max().unwrap_or(0);
+    let mut by_state: HashMap<u32, Vec<(u32, f64, f64)>> = HashMap::new();
+    for (cpu, start, end, state) in idle_bands {
+        by_state.entry(state).or_default().push((cpu, start, end));
+    }
+    let mut states: Vec<u32> = by_state.keys().copied().collect();
+    states.sort();
+
+    for state in states {
+        // the bands of a c-state are a single trace, split by gaps
+        let (mut xs, mut ys, mut hover_text) = (Vec::new(), Vec::new(), Vec::new());
+        for (cpu, start, end) in &by_state[&state] {
+            let Some(row) = y_axis.get(cpu) else { continue };
+            let text = format!("Cpu: {}<br>C-state: {}<br>Idle from: {:.*}<br>To: {:.*}",
+                                cpu, state, options.timestamp_precision, start - hover_orig, options.timestamp_precision, end - hover_orig);
+            xs.extend([Some(start - orig), Some(end - orig), None]);
+            ys.extend([Some(*row), Some(*row), None]);
+            hover_text.extend([text.clone(), text, String::new()]);
+        }
+        let shade = (200.0 - 150.0 * state as f64 / deepest.max(1) as f64) as u8;
+        plot.add_trace(Scatter::new(xs, ys)
+            .mode(Mode::Lines)
+            .name(format!("idle C{}", state))
+            .legend_group(format!("idle C{}", state))
+            .line(Line::new().color(Rgb::new(shade, shade, shade)).width(options.line_width))
+            .opacity(0.5)
+            .hover_text_array(hover_text)
+            .hover_info(HoverInfo::Text)
+            .web_gl_mode(options.webgl));
+    }
+}
+
+// step line of the runnable task count, on the secondary y axis
+fn draw_runqueue_depth(orig: f64, hover_orig: f64, plot: &mut Plot, runqueue_depth: Vec<(f64, u32)>, options: &Graph) {
+    let hover_text: Vec<String> = runqueue_depth.iter()
+                                    .map(|(timestamp, depth)| format!("Timestamp: {:.*}<br>Runnable tasks: {}", options.timestamp_precision, timestamp - hover_orig, depth))
+                                    .collect();
+    let (timestamps, depths): (Vec<f64>, Vec<u32>) = runqueue_depth.into_iter()
+                                                        .map(|(timestamp, depth)| (timestamp - orig, depth))
+                                                        .unzip();
+
+    plot.add_trace(Scatter::new(timestamps, depths)
+        .mode(Mode::Lines)
+        .name("runqueue depth")
+        .y_axis("y2")
+        .line(Line::new().color(NamedColor::DimGray).shape(LineShape::Hv))
+        .hover_text_array(hover_text)
+        .hover_info(HoverInfo::Text)
+        .web_gl_mode(options.webgl));
+}
+
+// fraction of the plot height taken by the activity strip
+const ACTIVITY_STRIP_HEIGHT: f64 = 0.05;
+
+// heat strip of the events of every activity_bucket window, on the y4 axis above the cpu rows
+// Windows without events between the first and last one are drawn at 0
+fn draw_activity_strip(plot: &mut Plot, activity: HashMap<i64, u32>, options: &Graph) {
+    let bucket = options.activity_bucket;
+    if bucket <= 0.0 {
+        panic!("activity_bucket must be positive, got {}", bucket);
+    }
+    let (Some(first), Some(last)) = (activity.keys().min().copied(), activity.keys().max().copied()) else {
+        return;
+    };
+    let xs: Vec<f64> = (first..=last).map(|window| (window as f64 + 0.5) * bucket).collect();
+    let counts: Vec<u32> = (first..=last).map(|window| activity.get(&window).copied().unwrap_or(0)).collect();
+
+    plot.add_trace(HeatMap::new(xs, vec!["events"], vec![counts])
+        .name("activity")
+        .y_axis("y4")
+        .color_scale(ColorScale::Palette(ColorScalePalette::YlOrRd))
+        .reverse_scale(true)
+        .show_scale(false)
+        .hover_template(format!("%{{z}} events in {} seconds<extra></extra>", bucket).as_str()));
+}
+
+// y axis of the activity strip, a thin row above the cpu axis
+fn activity_axis() -> Axis {
+    Axis::new()
+        .domain(&[1.0 - ACTIVITY_STRIP_HEIGHT, 1.0])
+        .anchor(String::from("x"))
+        .show_tick_labels(false)
+        .fixed_range(true)
+        .show_grid(false)
+}
+
+// switches per second of every displayed cpu, over windows of switch_rate_window seconds, on the y3 axis
+// Windows without switches between the first and last switch of the cpu are drawn at 0
+fn draw_switch_rate(orig: f64, plot: &mut Plot, switch_events: &HashMap<u32, Vec<&Action>>, y_axis: &HashMap<u32, u32>, options: &Graph) {
+    let window = options.switch_rate_window;
+    if window <= 0.0 {
+        panic!("switch_rate_window must be positive, got {}", window);
+    }
+
+    let mut cpus: Vec<&u32> = switch_events.keys().filter(|cpu| y_axis.contains_key(cpu)).collect();
+    cpus.sort();
+    for (index, cpu) in cpus.into_iter().enumerate() {
+        let mut counts: HashMap<i64, u32> = HashMap::new();
+        for action in &switch_events[cpu] {
+            *counts.entry(((action.timestamp - orig) / window).floor() as i64).or_insert(0) += 1;
+        }
+        let (Some(first), Some(last)) = (counts.keys().min().copied(), counts.keys().max().copied()) else {
+            continue;
+        };
+
+        let mut xs = Vec::new();
+        let mut rates = Vec::new();
+        let mut hover_text = Vec::new();
+        for bin in first..=last {
+            let rate = counts.get(&bin).copied().unwrap_or(0) as f64 / window;
+            xs.push((bin as f64 + 0.5) * window);
+            rates.push(rate);
+            hover_text.push(format!("Cpu: {}<br>Switches/s: {:.0}", cpu, rate));
+        }
+        plot.add_trace(Scatter::new(xs, rates)
+            .mode(Mode::Lines)
+            .name("switch rate")
+            .legend_group("switch rate")
+            .show_legend(index == 0)
+            .y_axis("y3")
+            .line(Line::new().color(NamedColor::SlateGray).width(1.0))
+            .opacity(0.4)
+            .hover_text_array(hover_text)
+            .hover_info(HoverInfo::Text)
+            .web_gl_mode(options.webgl));
+    }
+}
+
+// secondary y axis for the switch rate overlay
+// With the runqueue depth overlay also shown, it is moved right of the runqueue axis
+fn switch_rate_axis(runqueue_depth: bool) -> Axis {
+    let axis = Axis::new()
+        .title(Title::new("Switches/s"))
+        .overlaying("y")
+        .side(AxisSide::Right)
+        .range_mode(RangeMode::ToZero)
+        .show_grid(false);
+    if runqueue_depth {
+        axis.anchor(String::from("free")).position(1.0)
+    } else {
+        axis
+    }
+}
+
+// secondary y axis for the runqueue depth overlay
+fn runqueue_axis() -> Axis {
+    Axis::new()
+        .title(Title::new("Runnable tasks"))
+        .overlaying("y")
+        .side(AxisSide::Right)
+        .range_mode(RangeMode::ToZero)
+        .show_grid(false)
+}
+
+// one line summary of what was read from the trace
+// switch segments merged with merge_adjacent_below are only mentioned when there are some
+fn print_parse_stats(filename: &str, reader: &TraceParser, duplicate_switches: usize, merged_segments: usize, elapsed: Duration) {
+    let mut counts: Vec<(&&str, &u64)> = reader.event_counts.iter().collect();
+    counts.sort();
+    let counts: Vec<String> = counts.iter().map(|(name, count)| format!("{}: {}", name, count)).collect();
+    let parsed: u64 = reader.event_counts.values().sum();
+
+    let merged = if merged_segments > 0 { format!(", {} switch segments merged into the previous ones", merged_segments) } else { String::new() };
+    println!("{}: {} lines read, {} events parsed ({}), {} not supported, {} malformed, {} wakeups without fork, {} duplicate switches dropped{}, parsed in {:.3?}",
+                filename, reader.lines_read, parsed, counts.join(", "), reader.unsupported, reader.malformed, reader.orphaned_wakeups, duplicate_switches, merged, elapsed);
+}
+
+// event types that are not parsed with their count, most frequent first
+fn unsupported_summary(reader: &TraceParser) -> String {
+    let mut types: Vec<(&String, &u64)> = reader.unsupported_types.iter().collect();
+    types.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    types.iter().map(|(name, count)| format!("{} ({})", name, count)).collect::<Vec<String>>().join(", ")
+}
+
+// parse a file and draw its traces on the plot
+// the lifetime companion plot is written here as it only depends on the file
+// parser of a drawn file with its cpu rows and the utilization of each cpu
+pub(crate) type DrawnFile = (TraceParser, HashMap<u32, u32>, HashMap<u32, f64>);
+
+// None when the file has no event to plot
+pub(crate) fn draw_file(filepath: &str, config: &Config, plot: &mut Plot) -> Option<DrawnFile> {
     let options = &config.graph;
     let filename = filepath.split("/").last().unwrap();
-    let mut plot = Plot::new();
+    let mut lifetime_plot = Plot::new();
+
+    let parse_start = Instant::now();
+    let ((reader, y_axis, utilization), duplicate_switches, merged_segments) = draw_traces(filepath, config, plot, &mut lifetime_plot)?;
+    let parse_time = parse_start.elapsed();
+    if interrupted() {
+        eprintln!("Warning: the parsing of {} was interrupted, it is only plotted up to the event at {:.*}",
+                    filename, options.timestamp_precision, reader.last_timestamp.unwrap_or_default());
+    }
+    if reader.out_of_order > 0 {
+        eprintln!("Warning: {} events in {} have out of order timestamps", reader.out_of_order, filename);
+    }
+    if reader.sessions > 1 && !options.split_sessions {
+        eprintln!("Warning: {} has {} concatenated sessions, drawn over each other, split_sessions plots them apart", filename, reader.sessions);
+    }
+    if reader.unsupported > 0 {
+        eprintln!("Warning: skipped {} events of types: {}", reader.unsupported, unsupported_summary(&reader));
+    }
+    if reader.malformed > 0 {
+        eprintln!("Warning: skipped {} lines of {} that cannot be parsed, the trace may have been cut short", reader.malformed, filename);
+        if let Some((offset, line)) = reader.parse_errors.first() {
+            eprintln!("  first one at byte {}: {}", offset, line);
+        }
+    }
+    check_skipped(filename, &reader, options);
 
-    let reader = draw_traces(filepath, config, &mut plot);
-    
-    let duration: Vec<f64>;
-    let x_axis_title: String;
+    // companion plot with the task lifetimes
+    if options.lifetime_view {
+        lifetime_plot.set_configuration(Configuration::display_logo(lifetime_plot.configuration().clone(), false));
+        if options.embed_plotlyjs {
+            lifetime_plot.use_local_plotly();
+        }
+        lifetime_plot.write_html(format!("{}{}-lifetime.html", options.output_path, filename));
+    }
+
+    if !options.quiet {
+        print_parse_stats(filename, &reader, duplicate_switches, merged_segments, parse_time);
+    }
+    Some((reader, y_axis, utilization))
+}
+
+// full height vertical lines with their label on top, at the annotation times
+// Times outside of the plotted range are dropped so they do not stretch the x axis
+// On a date axis, date_origin is the date of x = 0
+pub(crate) fn add_annotations(layout: &mut Layout, options: &Graph, range: &[f64], date_origin: Option<f64>) {
+    for (time, label) in &options.annotations {
+        if *time < range[0] || *time > range[1] {
+            eprintln!("Warning: annotation {} at {} is outside of the plot, from {} to {}", label, time, range[0], range[1]);
+            continue;
+        }
+        let time = &date_origin.map_or(*time, |origin| date_x(*time, origin));
+        layout.add_shape(Shape::new()
+                            .shape_type(ShapeType::Line)
+                            .x_ref("x")
+                            .y_ref("paper")
+                            .x0(*time)
+                            .x1(*time)
+                            .y0(0)
+                            .y1(1)
+                            .line(ShapeLine::new().color(NamedColor::DimGray).width(1.0).dash(DashType::Dash)));
+        layout.add_annotation(Annotation::new()
+                                .text(label)
+                                .x_ref("x")
+                                .y_ref("paper")
+                                .x(*time)
+                                .y(1.0)
+                                .y_anchor(Anchor::Bottom)
+                                .show_arrow(false));
+    }
+}
+
+// Date of x = 0, in milliseconds since the epoch, when trace_start_wallclock is set
+// The wall-clock time is the one of the first event of the file, even when the plot starts after it, like with sleep
+// It is shown as written, without time zone
+fn date_origin(options: &Graph, reader: &TraceParser) -> Option<f64> {
+    let wallclock = options.trace_start_wallclock.as_ref()?;
+    let start = DateTime::parse_from_rfc3339(wallclock)
+                    .unwrap_or_else(|err| panic!("Invalid trace_start_wallclock {}, expected RFC3339 like 2024-05-01T14:30:00.250+02:00: {}", wallclock, err));
+    let orig = time_origin(options, reader.start_timestamp.unwrap());
+    Some(start.naive_local().and_utc().timestamp_micros() as f64 / 1000.0 + (orig - reader.first_event_timestamp.unwrap()) * 1000.0)
+}
+
+// x in seconds to a date of the date axis
+fn date_x(x: f64, date_origin: f64) -> f64 {
+    date_origin + x * 1000.0
+}
+
+// the same traces on a date axis, with the date at the end of their hover text
+fn to_date_axis(plot: &Plot, date_origin: f64, precision: usize) -> Plot {
+    let fraction = if precision > 0 { format!(".%{}f", precision.min(6)) } else { String::new() };
+    let mut date_plot = Plot::new();
+    for trace in plot.data().iter() {
+        let mut json: Value = serde_json::from_str(&trace.to_json()).expect("Failed to read trace");
+        if let Some(xs) = json["x"].as_array() {
+            json["x"] = xs.iter().map(|x| x.as_f64().map_or(x.clone(), |x| Value::from(date_x(x, date_origin)))).collect();
+        }
+        if !json["hovertext"].is_null() && json["hoverinfo"] != "skip" {
+            json["hovertemplate"] = Value::from(format!("%{{hovertext}}<br>Time: %{{x|%Y-%m-%d %H:%M:%S{}}}<extra></extra>", fraction));
+        }
+        date_plot.add_trace(Box::new(JsonTrace(json)));
+    }
+    date_plot
+}
+
+// range and title of the duration axis
+pub(crate) fn duration_range(options: &Graph, reader: &TraceParser) -> (Vec<f64>, String) {
     if options.custom_range {
-        duration = vec![options.min, options.max];
-        x_axis_title = format!("Duration: {} seconds", options.max - options.min);
+        (vec![options.min, options.max], format!("Duration: {:.*} seconds", options.timestamp_precision, options.max - options.min))
     } else {
-        duration = vec![0.0, reader.last_timestamp.unwrap() - reader.first_timestamp.unwrap()];
-        x_axis_title = format!("Duration: {:.6?} seconds", reader.last_timestamp.unwrap() - reader.first_timestamp.unwrap())
+        // events before the start of the plot, from out of order timestamps, get negative times
+        let start = reader.first_timestamp.unwrap();
+        let end = reader.last_timestamp.unwrap();
+        let orig = time_origin(options, reader.start_timestamp.unwrap());
+        (vec![start - orig, end - orig], format!("Duration: {:.*} seconds", options.timestamp_precision, end - start))
     }
+}
 
+// x and y ranges bounding the first time the command runs, from the switch before it to its switch out
+// The command may have been exec'd while running, so the segment is found from its switch out
+// Only the view changes, the whole trace is still in the plot
+fn focus_range(filepath: &str, options: &Graph, command: &str, y_axis: &HashMap<u32, u32>, orig: f64) -> Option<(Vec<f64>, Vec<f64>)> {
+    let mut reader = TraceParser::new(filepath);
+    find_sleep(&mut reader, filepath, options);
+
+    let mut last_switch: HashMap<u32, f64> = HashMap::new();
+    let mut switched_in: Option<(f64, u32)> = None;
+    let mut segment = None;
+    while let Some((action, ..)) = reader.next_action() {
+        if let Events::SchedSwitch { old_command, new_command, .. } = &action.event {
+            if !y_axis.contains_key(&action.cpu) {
+                continue;
+            }
+            if old_command == command {
+                let start = last_switch.get(&action.cpu).copied().or(reader.first_timestamp).unwrap();
+                segment = Some((start, action.timestamp, action.cpu));
+                break;
+            }
+            if new_command == command && switched_in.is_none() {
+                switched_in = Some((action.timestamp, action.cpu));
+            }
+            last_switch.insert(action.cpu, action.timestamp);
+        }
+    }
+
+    // never switched out, it runs until the end of the trace
+    let segment = segment.or(switched_in.map(|(start, cpu)| (start, reader.last_timestamp.unwrap(), cpu)));
+    let Some((start, end, cpu)) = segment else {
+        eprintln!("Warning: focus command {} never runs on the displayed cpus", command);
+        return None;
+    };
+    let margin = ((end - start) * 0.1).max(1e-6);
+    let row = y_axis[&cpu] as f64;
+    Some((vec![start - orig - margin, end - orig + margin], vec![row - 1.0, row + 1.0]))
+}
+
+// y axis with one row per displayed cpu, or per numa node in socket view
+pub(crate) fn get_cpu_axis(config: &Config, reader: &TraceParser, y_axis: &HashMap<u32, u32>) -> Axis {
+    let options = &config.graph;
     let mut y_axis_title = String::from("Cores"); 
-    if options.socket_order {
+    if options.view == "socket" {
+        y_axis_title = String::from("Sockets");
+    }
+    else if !options.cpu_order.is_empty() {
+        y_axis_title.push_str(" (custom order)")
+    }
+    else if options.socket_order {
         y_axis_title.push_str(" (socket order)")
     }
 
-    let mut layout = Layout::new()
-                            .x_axis(
-                                Axis::new()
-                                .title(Title::new(&x_axis_title))
-                                .range(duration)
-                                .show_grid(false))
-                            .y_axis(
-                                Axis::new()
-                                .title(Title::new(&y_axis_title))
-                                .range(vec![0, reader.cpu_count - 1])
-                                .show_grid(false))
-                            .auto_size(true);
+    let cpu_axis = Axis::new()
+                        .title(Title::new(&y_axis_title))
+                        .show_grid(options.show_grid);
+    // the axis ends at the last drawn row, below the header cpu count with filtered cpus or a header over-reporting them
+    let pad = options.pad_y as f64;
+    let last_row = y_axis.values().max().copied().unwrap_or(0);
+    if let (Some(cpu), "cpu") = (options.single_cpu, options.view.as_str()) {
+        Axis::new()
+            .title(Title::new(&format!("Core {}", cpu)))
+            .show_grid(options.show_grid)
+            .range(vec![-0.5, 0.5])
+            .tick_values(vec![0.0])
+            .tick_text(vec![cpu.to_string()])
+    } else if options.view == "socket" {
+        // unexpected cpus are on rows past the last socket
+        let sockets = (config.machine.numa_node_ranges.len() as u32).max(last_row + 1);
+        cpu_axis.range(vec![-pad, last_row as f64 + pad])
+                .tick_values((0..sockets).map(|socket| socket as f64).collect())
+                .tick_text((0..sockets).map(|socket| format!("node{}", socket)).collect())
+    } else if y_axis.len() as u32 == reader.cpu_count && options.cpu_tick_step == 0 && options.cpu_order.is_empty() {
+        cpu_axis.range(vec![-pad, last_row as f64 + pad])
+    } else {
+        // rows may be compressed, so label them with the real cpu numbers
+        let (tick_values, tick_text) = cpu_tick_labels(y_axis, &config.machine, options.socket_order && options.cpu_order.is_empty(), options.cpu_tick_step);
+        cpu_axis.range(vec![-pad, last_row as f64 + pad])
+                .tick_values(tick_values)
+                .tick_text(tick_text)
+    }
+}
 
+// the call of the html template of plotly that takes the plot as json
+const NEW_PLOT: &str = "Plotly.newPlot(graph_div, ";
 
-    if options.line_marker_count > 0 && options.line_marker_count <= 25 {
-        layout = layout.hover_distance(100);
-    }
+// Html of the plot with the overrides merged into its layout
+// Layout cannot be built from json, so the plot json of the rendered html is replaced with the merged one
+fn html_with_layout(plot: &Plot, overrides: &Value) -> String {
+    let html = plot.to_html();
+    let mut json: Value = serde_json::from_str(&plot.to_json()).expect("Failed to read plot");
+    merge_json(&mut json["layout"], overrides);
 
-    if options.show_title {
-        layout = layout.title(Title::new(format!("Data Graph: {}", filename).as_str()));
+    let start = html.find(NEW_PLOT).expect("No plot in the html template") + NEW_PLOT.len();
+    // the json has no raw line breaks, so the first one after the call ends it
+    let end = start + html[start..].find(");\n").expect("No plot in the html template");
+    // a closing script tag in a string would end the script
+    format!("{}{}{}", &html[..start], json.to_string().replace("</", "<\\/"), &html[end..])
+}
+
+// objects are merged key by key, any other value of the overrides replaces the one of the target
+fn merge_json(target: &mut Value, overrides: &Value) {
+    match (target, overrides) {
+        (Value::Object(target), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_json(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, overrides) => *target = overrides.clone(),
     }
+}
 
+// write the plot as html and static image named after name, and show it if needed
+pub(crate) fn write_plot(plot: &mut Plot, options: &Graph, name: &str) {
     plot.set_configuration(Configuration::display_logo(plot.configuration().clone(), false));
     plot.set_configuration(Configuration::fill_frame(plot.configuration().clone(), true));
 
@@ -626,18 +2192,29 @@ pub fn data_graph(filepath: &str, config: &Config) {
         plot.set_configuration(Configuration::static_plot(plot.configuration().clone(), true));
     }
 
-    plot.set_layout(layout);
-    plot.use_local_plotly();
-    if options.show_html && options.browser == "" {
+    if options.embed_plotlyjs {
+        plot.use_local_plotly();
+    }
+    // never open a browser in headless mode, the files are still written
+    let show = options.show_html && !options.headless;
+    let overrides = options.plotly_layout_overrides.as_ref().map(|overrides| serde_json::from_str::<Value>(overrides).expect("Invalid plotly_layout_overrides"));
+    // plot.show() writes its own html, without the overrides
+    if show && options.browser.is_empty() && overrides.is_none() {
         plot.show();
     }
 
+    let html_path = format!("{}{}.html", options.output_path, name);
     if options.create_html || options.show_html {
-        plot.write_html(format!("{}{}.html", options.output_path, filename));
+        match &overrides {
+            Some(overrides) => std::fs::write(&html_path, html_with_layout(plot, overrides)).expect("Error while writing html"),
+            None => plot.write_html(&html_path),
+        }
     }
 
-    if options.show_html && options.browser != "" {
-        open::with(format!("{}{}.html", options.output_path, filename), options.browser.to_string()).expect("Could not open alternate browser");
+    if show && !options.browser.is_empty() {
+        open::with(&html_path, options.browser.to_string()).expect("Could not open alternate browser");
+    } else if show && overrides.is_some() {
+        open::that(&html_path).expect("Could not open browser");
     }
 
     if options.static_options.gen_static {
@@ -650,6 +2227,448 @@ pub fn data_graph(filepath: &str, config: &Config) {
             "eps" => ImageFormat::EPS,
             _ => { panic!("Invalid static file format"); }
         };
-        plot.write_image(format!("{}{}.{}", options.output_path, filename, options.static_options.filetype), image_format, options.static_options.static_res_width, options.static_options.static_res_height, 1.0);
+        let widened = (options.static_options.min_segment_width > 0.0)
+                        .then(|| widen_short_segments(plot, options.static_options.min_segment_width, options.static_options.static_res_width));
+        widened.as_ref().unwrap_or(plot)
+            .write_image(format!("{}{}.{}", options.output_path, name, options.static_options.filetype), image_format, options.static_options.static_res_width, options.static_options.static_res_height, options.static_options.static_scale);
+    }
+}
+
+// Copy of the plot for a static image, where the horizontal segments shorter than min_width pixels of the image
+// are widened around their middle, a static image cannot be zoomed in to see them
+// The pixel duration comes from the x axis range, or the time span of the traces, x values that are dates are left as they are
+fn widen_short_segments(plot: &Plot, min_width: f64, image_width: usize) -> Plot {
+    let mut traces: Vec<Value> = plot.data().iter().map(|trace| serde_json::from_str(&trace.to_json()).expect("Failed to read trace")).collect();
+    let layout = serde_json::to_value(plot.layout()).expect("Failed to read layout");
+    let span = match layout["xaxis"]["range"].as_array().map(|range| range.iter().filter_map(Value::as_f64).collect::<Vec<f64>>()) {
+        Some(range) if range.len() == 2 => range[1] - range[0],
+        _ => {
+            let xs = traces.iter().filter_map(|trace| trace["x"].as_array()).flatten().filter_map(Value::as_f64);
+            let (start, end) = xs.fold((f64::MAX, f64::MIN), |(start, end), x| (start.min(x), end.max(x)));
+            end - start
+        }
+    };
+    let min_duration = span * min_width / image_width as f64;
+
+    for trace in traces.iter_mut().filter(|trace| trace["mode"] == "lines") {
+        let (Some(xs), Some(ys)) = (trace["x"].as_array().cloned(), trace["y"].as_array()) else {
+            continue;
+        };
+        let mut widened = xs.clone();
+        for i in 0..xs.len().saturating_sub(1) {
+            // a segment on its own, the points of a trace being separated by null gaps
+            let alone = (i == 0 || xs[i - 1].is_null()) && xs.get(i + 2).is_none_or(Value::is_null);
+            let (Some(start), Some(end)) = (xs[i].as_f64(), xs[i + 1].as_f64()) else {
+                continue;
+            };
+            if alone && !ys[i].is_null() && ys.get(i + 1) == Some(&ys[i]) && end - start < min_duration {
+                let middle = (start + end) / 2.0;
+                widened[i] = Value::from(middle - min_duration / 2.0);
+                widened[i + 1] = Value::from(middle + min_duration / 2.0);
+            }
+        }
+        trace["x"] = Value::from(widened);
+    }
+
+    let mut widened = Plot::new();
+    for trace in traces {
+        widened.add_trace(Box::new(JsonTrace(trace)));
+    }
+    widened.set_layout(plot.layout().clone());
+    widened.set_configuration(plot.configuration().clone());
+    widened
+}
+
+// footer with the path, size and sha-256 of the trace, the graph config it was plotted with is its hover text
+fn provenance(filepath: &str, options: &Graph) -> Annotation {
+    let mut file = File::open(filepath).expect("Failed to open file");
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher).expect("Failed to read file");
+    let hash: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    let config = serde_json::to_string_pretty(options).expect("Failed to serialize config");
+
+    Annotation::new()
+        .text(format!("Trace: {}, {} bytes, sha256 {}", filepath, size, hash))
+        .hover_text(config.replace('\n', "<br>"))
+        .x_ref("paper")
+        .y_ref("paper")
+        .x(0.0)
+        .y(0.0)
+        .x_anchor(Anchor::Left)
+        .y_anchor(Anchor::Top)
+        .y_shift(-40.0)
+        .font(Font::new().size(10).color(NamedColor::DimGray))
+        .show_arrow(false)
+}
+
+pub fn data_graph(filepath: &str, config: &Config) {
+    let options = &config.graph;
+    let filename = filepath.split("/").last().unwrap();
+    let mut plot = Plot::new();
+
+    let Some((reader, y_axis, utilization)) = draw_file(filepath, config, &mut plot) else {
+        eprintln!("Warning: no events to plot in {}", filename);
+        return;
+    };
+    let (duration, x_axis_title) = duration_range(options, &reader);
+
+    let mut x_range = duration.clone();
+    let mut y_axis_layout = get_cpu_axis(config, &reader, &y_axis);
+    if let Some(command) = &options.focus_command {
+        let orig = time_origin(options, reader.start_timestamp.unwrap());
+        if let Some((focus_x, focus_y)) = focus_range(filepath, options, command, &y_axis, orig) {
+            x_range = focus_x;
+            y_axis_layout = y_axis_layout.range(focus_y);
+        }
+    }
+
+    // the cpu rows make room for the activity strip above them
+    if options.activity_strip {
+        y_axis_layout = y_axis_layout.domain(&[0.0, 0.97 - ACTIVITY_STRIP_HEIGHT]);
+    }
+    let mut x_axis = Axis::new()
+                        .title(Title::new(&x_axis_title))
+                        .show_grid(options.show_grid);
+    // room on the right for both overlay axes
+    if options.runqueue_depth && options.show_switch_rate {
+        x_axis = x_axis.domain(&[0.0, 0.92]);
+    }
+    // wall-clock dates instead of seconds
+    let date_origin = date_origin(options, &reader);
+    if let Some(origin) = date_origin {
+        plot = to_date_axis(&plot, origin, options.timestamp_precision);
+        x_range = x_range.iter().map(|x| date_x(*x, origin)).collect();
+        x_axis = x_axis.type_(AxisType::Date);
+    }
+
+    let mut layout = Layout::new()
+                            .x_axis(x_axis.range(x_range))
+                            .y_axis(y_axis_layout)
+                            .auto_size(true);
+
+    if options.runqueue_depth {
+        layout = layout.y_axis2(runqueue_axis());
+    }
+    if options.activity_strip {
+        layout = layout.y_axis4(activity_axis());
+    }
+    if options.show_switch_rate {
+        layout = layout.y_axis3(switch_rate_axis(options.runqueue_depth));
+    }
+
+    if options.line_marker_count > 0 && options.line_marker_count <= 25 {
+        layout = layout.hover_distance(100);
+    }
+    add_annotations(&mut layout, options, &duration, date_origin);
+    if options.embed_provenance {
+        layout.add_annotation(provenance(filepath, options));
+    }
+    if options.show_utilization {
+        for annotation in utilization_annotations(&utilization, &y_axis, "y") {
+            layout.add_annotation(annotation);
+        }
+    }
+    // the socket view already has a row per node
+    if options.node_separators && options.view == "cpu" {
+        let (shapes, annotations) = node_separators(&y_axis, &config.machine, "y");
+        shapes.into_iter().for_each(|shape| layout.add_shape(shape));
+        annotations.into_iter().for_each(|annotation| layout.add_annotation(annotation));
+    }
+
+    if options.show_title {
+        layout = layout.title(Title::new(format!("Data Graph: {}", filename).as_str()));
+    }
+
+    plot.set_layout(layout);
+    let write_start = Instant::now();
+    write_plot(&mut plot, options, filename);
+    benchmark::record("plot write", write_start.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2 sockets of 2 cpus
+    fn machine() -> Machine {
+        Machine { cpus: 4, sockets: 2, cores_per_socket: 2, threads_per_core: 1, numa_nodes: 2, numa_node_ranges: vec![vec![vec![0, 1]], vec![vec![2, 3]]], node_hops: None }
+    }
+
+    fn fork(pid: u32, child_pid: u32) -> Action {
+        let event = Events::SchedProcessFork { command: String::from("app"), pid, child_command: String::from("app"), child_pid };
+        Action { process: String::from("app"), pid, cpu: 0, timestamp: 100.0, event }
+    }
+
+    #[test]
+    fn colors_past_max_colors_are_gray() {
+        let counts = HashMap::from([(1, 5), (2, 1), (3, 5), (4, 3)]);
+        let colors = ranked_colors(counts, Some(2), &reserved_colors(&Graph::default()));
+        let other = serde_json::to_value(Rgb::new(OTHER_COLOR.0, OTHER_COLOR.1, OTHER_COLOR.2)).unwrap();
+        let gray = |pid| serde_json::to_value(colors[&pid]).unwrap() == other;
+        // 1 and 3 are tied, 2 has the fewest switches
+        assert!(!gray(1) && !gray(3));
+        assert!(gray(4) && gray(2));
+    }
+
+    #[test]
+    fn priority_classes_have_their_colors() {
+        let color = |prio| serde_json::to_value(prio_color(prio)).unwrap();
+        assert_eq!(color(Some(0)), serde_json::to_value(Rgb::new(139, 0, 0)).unwrap());
+        assert_eq!(color(Some(100)), serde_json::to_value(Rgb::new(8, 48, 107)).unwrap());
+        assert_eq!(color(Some(140)), serde_json::to_value(Rgb::new(IDLE_CLASS_COLOR.0, IDLE_CLASS_COLOR.1, IDLE_CLASS_COLOR.2)).unwrap());
+        // no priority in the trace
+        assert_eq!(color(None), serde_json::to_value(Rgb::new(OTHER_COLOR.0, OTHER_COLOR.1, OTHER_COLOR.2)).unwrap());
+    }
+
+    #[test]
+    fn an_interrupt_only_cuts_its_own_plot() {
+        let path = format!("{}/tests/fixtures/switch.txt", env!("CARGO_MANIFEST_DIR"));
+        let config = Config { machine: machine(), graph: Graph::default() };
+        // ctrl-c during an earlier plot
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            let ((reader, ..), ..) = draw_traces(&path, &config, &mut Plot::new(), &mut Plot::new()).unwrap();
+            assert_eq!(reader.event_counts.get("sched_switch"), Some(&4));
+            assert!(!interrupted());
+        }
+    }
+
+    #[test]
+    fn a_trace_with_only_its_header_has_nothing_to_draw() {
+        let path = format!("{}/tests/fixtures/header-only.txt", env!("CARGO_MANIFEST_DIR"));
+        let config = Config { machine: machine(), graph: Graph::default() };
+        assert!(draw_traces(&path, &config, &mut Plot::new(), &mut Plot::new()).is_none());
+    }
+
+    #[test]
+    fn wallclock_is_the_first_event_even_after_the_sleep_marker() {
+        let path = format!("{}/tests/fixtures/process.txt", env!("CARGO_MANIFEST_DIR"));
+        let options = Graph {
+            sleep: true,
+            start_marker_command: String::from("run"),
+            trace_start_wallclock: Some(String::from("2024-05-01T14:30:00Z")),
+            ..Graph::default()
+        };
+        let mut reader = TraceParser::new(&path);
+        find_sleep(&mut reader, &path, &options);
+        while reader.next_action().is_some() {}
+        // the plot starts at the exit of run, 200 microseconds after the first event at 14:30:00
+        let start = DateTime::parse_from_rfc3339("2024-05-01T14:30:00Z").unwrap().naive_local().and_utc().timestamp_micros() as f64 / 1000.0;
+        let origin = date_origin(&options, &reader).unwrap();
+        assert!((origin - start - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn skipped_lines_against_a_count_or_a_fraction() {
+        assert!(!skipped_past(0, 1000, 0.0));
+        assert!(skipped_past(1, 1000, 0.0));
+        assert!(!skipped_past(10, 1000, 10.0));
+        assert!(skipped_past(11, 1000, 10.0));
+        // below 1, a fraction of the lines read
+        assert!(!skipped_past(10, 1000, 0.01));
+        assert!(skipped_past(11, 1000, 0.01));
+    }
+
+    #[test]
+    fn commands_are_grouped_by_the_cgroup_regex() {
+        let regex = Regex::new("^(ctr-[0-9a-f]+)-").unwrap();
+        assert_eq!(cgroup_key(&regex, "ctr-3f2a-nginx"), "ctr-3f2a");
+        assert_eq!(cgroup_key(&regex, "ctr-3f2a-worker 2"), "ctr-3f2a");
+        assert_eq!(cgroup_key(&regex, "kworker/0:1"), "ungrouped");
+        // without a capture group the whole match is the key
+        assert_eq!(cgroup_key(&Regex::new("^[a-z]+").unwrap(), "kworker/0:1"), "kworker");
+    }
+
+    #[test]
+    fn legends_sorted_by_frequency_keep_ties_in_order() {
+        let mut legends = vec![(String::from("wakeup"), 3, ()), (String::from("waking"), 10, ()), (String::from("irq"), 3, ())];
+        sort_legends(&mut legends, "frequency_desc");
+        let names: Vec<&str> = legends.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(names, ["waking", "wakeup", "irq"]);
+        sort_legends(&mut legends, "name");
+        let names: Vec<&str> = legends.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(names, ["irq", "wakeup", "waking"]);
+    }
+
+    #[test]
+    fn event_colors_replace_the_defaults() {
+        let options = Graph {
+            event_colors: HashMap::from([(String::from("wakeup"), String::from("navy")), (String::from("off-socket load balancing"), String::from("#e66100"))]),
+            ..Graph::default()
+        };
+        let colors = event_colors(&options);
+        let color = |name: &str| serde_json::to_value(colors[name]).unwrap();
+        assert_eq!(color("wakeup"), serde_json::to_value(Rgb::new(0, 0, 128)).unwrap());
+        assert_eq!(color("off-socket<br>load balancing"), serde_json::to_value(Rgb::new(230, 97, 0)).unwrap());
+        // the other events keep their color
+        assert_eq!(color("irq"), serde_json::to_value(Rgb::new(210, 105, 30)).unwrap());
+    }
+
+    #[test]
+    fn task_colors_keep_away_from_an_overridden_event_color() {
+        let options = Graph {
+            event_colors: HashMap::from([(String::from("wakeup"), String::from("navy"))]),
+            ..Graph::default()
+        };
+        let reserved = reserved_colors(&options);
+        assert!(reserved.contains(&(0, 0, 128)));
+        for _ in 0..1000 {
+            let css = serde_json::to_value(random_color(&reserved)).unwrap();
+            let rgb = csscolorparser::parse(css.as_str().unwrap()).unwrap().to_rgba8();
+            assert!(color_distance((rgb[0], rgb[1], rgb[2]), (0, 0, 128)) >= MIN_COLOR_DISTANCE);
+        }
+    }
+
+    #[test]
+    fn node_hops_replace_the_line_of_nodes() {
+        let mut machine = machine();
+        assert_eq!(node_distance(0, 1, &machine), 1);
+        assert_eq!(max_node_distance(&machine), 1);
+        machine.node_hops = Some(vec![vec![0, 2], vec![2, 0]]);
+        assert_eq!(node_distance(0, 1, &machine), 2);
+        assert_eq!(max_node_distance(&machine), 2);
+        // the node of cpus outside of the machine config
+        assert_eq!(node_distance(0, 2, &machine), 2);
+    }
+
+    #[test]
+    fn utilization_is_clipped_to_the_window() {
+        let switch = |timestamp, new_pid| Action {
+            process: String::from("app"), pid: 0, cpu: 0, timestamp,
+            event: Events::SchedSwitch { old_command: String::new(), old_pid: 0, old_prio: None, state: String::from("R"), new_command: String::from("app"), new_pid, new_prio: None },
+        };
+        // busy from 1 to 3 and from 5 to the end of the trace at 12
+        let events = [switch(101.0, 10), switch(103.0, 0), switch(105.0, 10)];
+        let switch_events = HashMap::from([(0, events.iter().collect())]);
+        assert_eq!(cpu_utilization(&switch_events, &[0.0, 10.0], 100.0, 112.0)[&0], 0.7);
+        assert_eq!(cpu_utilization(&switch_events, &[2.0, 6.0], 100.0, 112.0)[&0], 0.5);
+    }
+
+    #[test]
+    fn close_segments_of_a_task_share_a_trace() {
+        let switch = |timestamp, old_pid, new_pid| Action {
+            process: String::from("app"), pid: old_pid, cpu: 0, timestamp,
+            event: Events::SchedSwitch { old_command: String::from("app"), old_pid, old_prio: None, state: String::from("S"), new_command: String::from("app"), new_pid, new_prio: None },
+        };
+        // 10 runs twice around a short idle, then 20 runs, then 10 again
+        let events = [switch(100.0, 0, 10), switch(101.0, 10, 0), switch(101.2, 0, 10), switch(102.0, 10, 20), switch(103.0, 20, 10), switch(104.0, 10, 0)];
+        let draw = |merge_adjacent_below| {
+            let options = Graph { merge_adjacent_below, ..Graph::default() };
+            let mut plot = Plot::new();
+            let mut switch_markers = ScatterObject::new(Mode::Markers, "switch", Rgb::new(0, 0, 0));
+            let switch_events = HashMap::from([(0, events.iter().collect())]);
+            let merged = draw_sched_switch(100.0, 100.0, switch_events, ColorTable::Prio, &mut plot, &mut switch_markers, &HashMap::from([(0, 0)]), &options, 10, &HashMap::new());
+            assert_eq!(switch_markers.xs.len(), 4);
+            (plot.data().len(), merged)
+        };
+        assert_eq!(draw(0.0), (4, 0));
+        assert_eq!(draw(0.5), (3, 1));
+    }
+
+    #[test]
+    fn short_segments_are_widened_for_static_images() {
+        let mut plot = Plot::new();
+        // a switch segment of 0.1ms, a longer one after a gap, and a migration line
+        plot.add_trace(Scatter::new(vec![Some(1.0), Some(1.0001), None, Some(2.0), Some(3.0)], vec![Some(0), Some(0), None, Some(0), Some(0)]).mode(Mode::Lines));
+        plot.add_trace(Scatter::new(vec![4.0, 4.0001], vec![0, 1]).mode(Mode::Lines));
+        plot.set_layout(Layout::new().x_axis(Axis::new().range(vec![0.0, 10.0])));
+
+        // a pixel of 1000 is 0.01 seconds
+        let widened = widen_short_segments(&plot, 1.0, 1000);
+        let x = |trace: usize| serde_json::from_str::<Value>(&widened.data().iter().nth(trace).unwrap().to_json()).unwrap()["x"].clone();
+        let (start, end) = (x(0)[0].as_f64().unwrap(), x(0)[1].as_f64().unwrap());
+        assert!((end - start - 0.01).abs() < 1e-9);
+        assert!(((start + end) / 2.0 - 1.00005).abs() < 1e-9);
+        assert_eq!(x(0)[3], 2.0);
+        assert_eq!(x(1), serde_json::json!([4.0, 4.0001]));
+    }
+
+    #[test]
+    fn layout_overrides_are_merged() {
+        let mut plot = Plot::new();
+        plot.set_layout(Layout::new().title(Title::new("trace")).font(Font::new().size(12)));
+        let overrides = serde_json::json!({ "font": { "family": "Courier New" }, "legend": { "orientation": "h" } });
+        let html = html_with_layout(&plot, &overrides);
+
+        let start = html.find(NEW_PLOT).unwrap() + NEW_PLOT.len();
+        let end = start + html[start..].find(");\n").unwrap();
+        let json: Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(json["layout"]["font"], serde_json::json!({ "size": 12, "family": "Courier New" }));
+        assert_eq!(json["layout"]["legend"]["orientation"], "h");
+        assert_eq!(json["layout"]["title"]["text"], "trace");
+    }
+
+    #[test]
+    fn threads_are_grouped_under_the_task_that_exec_d() {
+        // shell 10 forks 20 which execs, 20 clones 21 which clones 22
+        let forks = vec![fork(10, 20), fork(20, 21), fork(21, 22)];
+        let leaders = thread_groups(&forks, &HashSet::from([20]));
+        assert_eq!(leaders[&20], 20);
+        assert_eq!(leaders[&21], 20);
+        assert_eq!(leaders[&22], 20);
+        // without the exec, the tree goes up to the shell
+        assert_eq!(thread_groups(&forks, &HashSet::new())[&22], 10);
+    }
+
+    // every cpu of the machine has its own row, and the rows of a socket follow each other
+    fn assert_socket_rows(machine: &Machine) {
+        let y_axis = get_y_axis(machine, true, machine.cpus, &[], &[]);
+        let mut rows: Vec<u32> = y_axis.values().copied().collect();
+        rows.sort();
+        assert_eq!(rows, (0..machine.cpus).collect::<Vec<u32>>());
+        for socket in 0..machine.numa_node_ranges.len() as u32 {
+            let rows: Vec<u32> = y_axis.iter().filter(|(cpu, _)| get_socket_order(**cpu, machine).0 == socket).map(|(_, row)| *row).collect();
+            assert_eq!(rows.iter().max().unwrap() - rows.iter().min().unwrap() + 1, rows.len() as u32, "socket {}", socket);
+        }
+    }
+
+    #[test]
+    fn socket_order_rows() {
+        // the default config, the second range of a socket are the hyperthreads
+        let machine = Machine { cpus: 64, sockets: 2, cores_per_socket: 16, threads_per_core: 2, numa_nodes: 2,
+                                numa_node_ranges: vec![vec![vec![0, 15], vec![32, 47]], vec![vec![16, 31], vec![48, 63]]], node_hops: None };
+        assert_socket_rows(&machine);
+        let y_axis = get_y_axis(&machine, true, 64, &[], &[]);
+        assert_eq!((y_axis[&15], y_axis[&32], y_axis[&16], y_axis[&48]), (15, 16, 32, 48));
+
+        let machine = Machine { cpus: 32, sockets: 4, cores_per_socket: 4, threads_per_core: 2, numa_nodes: 4,
+                                numa_node_ranges: (0..4).map(|node| vec![vec![node * 4, node * 4 + 3], vec![16 + node * 4, 19 + node * 4]]).collect(),
+                                node_hops: None };
+        assert_socket_rows(&machine);
+
+        // uneven sockets, with more ranges than threads
+        let machine = Machine { cpus: 12, sockets: 2, cores_per_socket: 4, threads_per_core: 2, numa_nodes: 2,
+                                numa_node_ranges: vec![vec![vec![0, 5]], vec![vec![6, 7], vec![8, 8], vec![9, 11]]], node_hops: None };
+        assert_socket_rows(&machine);
+    }
+
+    #[test]
+    fn separators_between_the_nodes() {
+        let machine = machine();
+        let (shapes, annotations) = node_separators(&get_y_axis(&machine, false, 4, &[], &[]), &machine, "y");
+        assert_eq!((shapes.len(), annotations.len()), (1, 2));
+        assert_eq!(serde_json::to_value(&shapes[0]).unwrap()["y0"], 1.5);
+        // cpu 2 of node 1 between the cpus of node 0
+        let (shapes, annotations) = node_separators(&get_y_axis(&machine, false, 4, &[], &[0, 2, 1, 3]), &machine, "y");
+        assert!(shapes.is_empty() && annotations.is_empty());
+    }
+
+    #[test]
+    fn cpu_past_the_header_gets_a_row() {
+        let machine = machine();
+        let mut y_axis = get_y_axis(&machine, false, 2, &[], &[]);
+        assert!(!add_unexpected_cpu(&mut y_axis, 1, &machine, "cpu"));
+        // an event on cpu = cpu_count
+        assert!(add_unexpected_cpu(&mut y_axis, 2, &machine, "cpu"));
+        assert_eq!(y_axis[&2], 2);
+        assert!(add_unexpected_cpu(&mut y_axis, 7, &machine, "cpu"));
+        assert_eq!(y_axis[&7], 3);
+
+        let mut y_axis = get_socket_axis(&machine, 2, &[]);
+        assert!(add_unexpected_cpu(&mut y_axis, 2, &machine, "socket"));
+        assert_eq!(y_axis[&2], 1);
+        // outside of the machine config
+        assert!(add_unexpected_cpu(&mut y_axis, 7, &machine, "socket"));
+        assert_eq!(y_axis[&7], 2);
     }
 }
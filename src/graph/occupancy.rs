@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use crate::graph::parser::*;
+use crate::read_config::Graph;
+use plotly::common::{ Orientation, Title };
+use plotly::layout::{ Axis, Layout };
+use plotly::{ Bar, Plot };
+use super::write_plot;
+
+// On-cpu time of every command, summed over its tasks and the cpus
+// A task runs from the switch to it until the next switch on the same cpu,
+// the task still running at the end of the trace runs until last_timestamp
+fn on_cpu_time(switch_events: &HashMap<u32, Vec<&Action>>, last_timestamp: f64) -> HashMap<String, f64> {
+    let mut occupancy: HashMap<String, f64> = HashMap::new();
+    for events in switch_events.values() {
+        let ends = events.iter().skip(1).map(|action| action.timestamp).chain(std::iter::once(last_timestamp));
+        for (action, end) in events.iter().zip(ends) {
+            if let Events::SchedSwitch { new_command, new_pid, .. } = &action.event {
+                if *new_pid != 0 && end > action.timestamp {
+                    *occupancy.entry(new_command.clone()).or_insert(0.0) += end - action.timestamp;
+                }
+            }
+        }
+    }
+    occupancy
+}
+
+// Companion chart with a horizontal bar of on-cpu time per command, the largest on top
+pub fn occupancy_chart(switch_events: &HashMap<u32, Vec<&Action>>, last_timestamp: f64, options: &Graph, filename: &str) {
+    let mut occupancy: Vec<(String, f64)> = on_cpu_time(switch_events, last_timestamp).into_iter().collect();
+    // plotly draws the first bar at the bottom
+    occupancy.sort_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+
+    let hover_text: Vec<String> = occupancy.iter()
+                                    .map(|(command, time)| format!("Command: {}<br>On cpu: {:.*} seconds", command, options.timestamp_precision, time))
+                                    .collect();
+    let (commands, times): (Vec<String>, Vec<f64>) = occupancy.into_iter().unzip();
+
+    let mut plot = Plot::new();
+    plot.add_trace(Bar::new(times, commands)
+                    .orientation(Orientation::Horizontal)
+                    .hover_text_array(hover_text)
+                    .name("on cpu"));
+
+    let mut layout = Layout::new()
+                        .x_axis(
+                            Axis::new()
+                            .title(Title::new("On-cpu time (seconds)"))
+                            .show_grid(options.show_grid))
+                        .y_axis(
+                            Axis::new()
+                            .title(Title::new("Commands"))
+                            .show_grid(false))
+                        .auto_size(true);
+    if options.show_title {
+        layout = layout.title(Title::new(format!("Cpu occupancy: {}", filename).as_str()));
+    }
+    plot.set_layout(layout);
+    write_plot(&mut plot, options, &format!("{}-occupancy", filename));
+}
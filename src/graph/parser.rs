@@ -1,18 +1,15 @@
 use core::panic;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Seek, SeekFrom};
 use std::path::Path;
-
-#[derive(Debug, Clone, Copy)]
-pub enum Wstate {
-    Waking(u32, u32),
-    Woken,
-    Numa(i32, i32)
-}
+use std::str::FromStr;
+pub use super::wstate::Wstate;
+pub use super::task_state::{ classify_state, parse_task_state, TaskState, TASK_STATES };
+use super::wstate::{ transition, RawEvent };
 
 // The different event types in the trace
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Events {
     // unblock - exec
     SchedWaking {
@@ -34,6 +31,8 @@ pub enum Events {
         pid: u32,
         parent_cpu: u32,
         cpu: u32,
+        // false when the fork was not in the trace, parent_cpu is then the target cpu
+        forked: bool,
     },
     SchedMigrateTask {
         command: String, 
@@ -45,9 +44,12 @@ pub enum Events {
     SchedSwitch {
         old_command: String, 
         old_pid: u32,
+        // priority in brackets after the pid, None if the line has no such field
+        old_prio: Option<u32>,
         state: String,
         new_command: String, 
         new_pid: u32,
+        new_prio: Option<u32>,
     },
 
     // process lifetime
@@ -93,12 +95,67 @@ pub enum Events {
         src_cpu: i32,
         dst_cpu: i32,
     },
-    // other
-    NotSupported
+    // priority inheritance, a task holding a lock is boosted to the priority of its waiter
+    SchedPiSetprio {
+        command: String,
+        pid: u32,
+        old_prio: i32,
+        new_prio: i32,
+    },
+    // interrupts
+    IrqHandlerEntry {
+        irq: u32,
+        name: String,
+        cpu: u32,
+    },
+    SoftirqEntry {
+        vec: u32,
+        action: String,
+        cpu: u32,
+    },
+    // power
+    // state is the c-state entered, or IDLE_EXIT when the cpu leaves idle
+    CpuIdle {
+        state: u32,
+        cpu: u32,
+    },
+
+    // other, with the event type that is not parsed
+    NotSupported(String)
+}
+
+impl Events {
+    // name of the trace event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Events::SchedWaking { .. } => "sched_waking",
+            Events::SchedWakeIdleNoIpi { .. } => "sched_wake_idle_without_ipi",
+            Events::SchedWakeup { .. } => "sched_wakeup",
+            Events::SchedWakeupNew { .. } => "sched_wakeup_new",
+            Events::SchedMigrateTask { .. } => "sched_migrate_task",
+            Events::SchedSwitch { .. } => "sched_switch",
+            Events::SchedProcessFree { .. } => "sched_process_free",
+            Events::SchedProcessExec { .. } => "sched_process_exec",
+            Events::SchedProcessFork { .. } => "sched_process_fork",
+            Events::SchedProcessWait { .. } => "sched_process_wait",
+            Events::SchedProcessExit { .. } => "sched_process_exit",
+            Events::SchedSwapNuma { .. } => "sched_swap_numa",
+            Events::SchedStickNuma { .. } => "sched_stick_numa",
+            Events::SchedMoveNuma { .. } => "sched_move_numa",
+            Events::SchedPiSetprio { .. } => "sched_pi_setprio",
+            Events::IrqHandlerEntry { .. } => "irq_handler_entry",
+            Events::SoftirqEntry { .. } => "softirq_entry",
+            Events::CpuIdle { .. } => "cpu_idle",
+            Events::NotSupported(_) => "not supported",
+        }
+    }
 }
 
+// cpu_idle state of a cpu leaving idle, written as -1 by the kernel
+pub const IDLE_EXIT: u32 = u32::MAX;
+
 // An Action represents a line of the trace
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Action {
     pub process: String,
     pub pid: u32,
@@ -115,241 +172,1013 @@ where P: AsRef<Path>, {
     Ok(io::BufReader::new(file).lines())
 }
 
+// lines read at the start of the trace looking for its cpus= header
+const HEADER_LINES: usize = 8;
+
+// the binary search of seek_to_timestamp stops within this many bytes of the timestamp, read line by line from there
+const SEEK_PRECISION: u64 = 64 * 1024;
+
+// malformed lines kept in parse_errors, the others are only counted
+const MAX_PARSE_ERRORS: usize = 100;
+
 pub struct TraceParser {
     pub cpu_count: u32,
+    // Origin of the plot: the first timestamp read, or the start marker
+    pub start_timestamp: Option<f64>,
+    // Smallest and largest timestamps read so far
+    // With out of order events these are not the first and last lines of the trace
     pub first_timestamp: Option<f64>,
     pub last_timestamp: Option<f64>,
-    lines: io::Lines<io::BufReader<File>>,
+    // Timestamp of the first event of the file, kept when the plot starts later, like after the sleep marker
+    pub first_event_timestamp: Option<f64>,
+    // Timestamp of the previous action, to detect out of order events
+    previous_timestamp: Option<f64>,
+
+    // Number of actions with a timestamp smaller than the one before them
+    pub out_of_order: u32,
+    // Panic on the first out of order timestamp instead of counting it
+    pub strict_order: bool,
+    // classes of unusual task state strings, the others are classified by their first letter
+    pub task_states: HashMap<String, TaskState>,
+
+    // Statistics of the lines read so far
+    pub lines_read: u64,
+    // reports in the file, each starting with its cpus= header
+    pub sessions: u32,
+    pub unsupported: u64,
+    // count of every event type that is not parsed, to tell which events the plot is missing
+    pub unsupported_types: HashMap<String, u64>,
+    // wakeup_new events of tasks whose fork is not in the trace
+    pub orphaned_wakeups: u64,
+    // event lines that cannot be parsed and are skipped, like a last line cut short while the trace was written
+    pub malformed: u64,
+    // byte offset and content of the first malformed lines, offsets stay valid when the trace is read from custom_range
+    pub parse_errors: Vec<(u64, String)>,
+    pub event_counts: HashMap<&'static str, u64>,
+    reader: io::BufReader<File>,
+    // byte offset of the next line in the file
+    position: u64,
 
     // Track the waking state of processes
     process_state: HashMap<u32, Wstate>,
+
+    // Cpu whose runqueue each runnable or running task is on, and the resulting depth of each runqueue
+    runqueue: HashMap<u32, u32>,
+    runqueue_depth: Vec<u32>,
 }
 
 impl TraceParser {
     // open trace file and get the cpu_count
     pub fn new(filepath: &str) -> Self {
-        let file = File::open(filepath).expect("Failed to open file");
-        let reader = io::BufReader::new(file);
-        let mut lines = reader.lines();
+        Self::try_new(filepath).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        let cpu_count = if let Some(Ok(line)) = lines.next() {
-            let part: Vec<&str> = line.split_whitespace().collect();
-            if part.len() > 0 && part[0].contains("cpus=") {
-                part[0].replace("cpus=", "").parse().unwrap()
-            } else {
-                panic!("Invalid format: Expected 'cpus=' in the first line");
+    // same as new(), but returns the error instead of panicking
+    pub fn try_new(filepath: &str) -> Result<Self, String> {
+        let file = File::open(filepath).map_err(|err| format!("Failed to open file: {}", err))?;
+        let mut reader = io::BufReader::new(file);
+        let mut position = 0;
+        let mut cpu_count = None;
+        // blank lines, comments and the version line of trace-cmd report can come before the header
+        for _ in 0..HEADER_LINES {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => position += read as u64,
+            }
+            if let Some(count) = header_cpu_count(&line) {
+                cpu_count = Some(count?);
+                break;
+            }
+        }
+        let Some(cpu_count) = cpu_count else {
+            if position == 0 {
+                return Err(String::from("Unable to read trace"));
             }
-        } else {
-            panic!("Unable to read trace");
+            return Err(format!("Invalid format: Expected 'cpus=' in the first {} lines", HEADER_LINES));
         };
 
-        TraceParser {
+        Ok(TraceParser {
             cpu_count,
+            start_timestamp: None,
             first_timestamp: None,
             last_timestamp: None,
-            lines,
+            first_event_timestamp: None,
+            previous_timestamp: None,
+            out_of_order: 0,
+            strict_order: false,
+            task_states: HashMap::new(),
+            lines_read: 0,
+            sessions: 1,
+            unsupported: 0,
+            unsupported_types: HashMap::new(),
+            orphaned_wakeups: 0,
+            malformed: 0,
+            parse_errors: Vec::new(),
+            event_counts: HashMap::new(),
+            position,
+            reader,
             process_state: HashMap::new(),
-        }
+            runqueue: HashMap::new(),
+            runqueue_depth: vec![0; cpu_count as usize],
+        })
     }
 
-    // read a line of trace and return the action, first timestamp and waking states of processes
+    // read a line of trace and return the action, waking states of processes and start timestamp
     pub fn next_action(&mut self) -> Option<(Action, &HashMap<u32, Wstate>, Option<f64>)> {
-        while let Some(Ok(line)) = self.lines.next() {
+        loop {
+            let offset = self.position;
+            let line = self.next_line()?;
+            self.lines_read += 1;
+            if let Some(cpus) = line.trim().strip_prefix("cpus=") {
+                self.start_session(cpus);
+                continue;
+            }
             let part: Vec<&str> = line.split_whitespace().collect();
-            if part.len() > 2 {
-                let action = get_action(&part, &mut self.process_state);
-                if self.first_timestamp.is_none() {
-                    self.first_timestamp = Some(action.timestamp);
+            if part.len() <= 2 {
+                continue;
+            }
+            let Some(action) = get_action(&part, &mut self.process_state) else {
+                self.malformed += 1;
+                if self.parse_errors.len() < MAX_PARSE_ERRORS {
+                    self.parse_errors.push((offset, line.trim().to_string()));
+                }
+                continue;
+            };
+            self.update_runqueue(&action);
+            if let Events::NotSupported(event_type) = &action.event {
+                self.unsupported += 1;
+                *self.unsupported_types.entry(event_type.clone()).or_insert(0) += 1;
+            } else {
+                *self.event_counts.entry(action.event.name()).or_insert(0) += 1;
+            }
+            if let Events::SchedWakeupNew { forked: false, .. } = action.event {
+                self.orphaned_wakeups += 1;
+            }
+            if self.start_timestamp.is_none() {
+                self.start_timestamp = Some(action.timestamp);
+            }
+            if self.first_event_timestamp.is_none() {
+                self.first_event_timestamp = Some(action.timestamp);
+            }
+            self.first_timestamp = Some(self.first_timestamp.map_or(action.timestamp, |first| first.min(action.timestamp)));
+            self.last_timestamp = Some(self.last_timestamp.map_or(action.timestamp, |last| last.max(action.timestamp)));
+            if let Some(previous) = self.previous_timestamp {
+                if action.timestamp < previous {
+                    if self.strict_order {
+                        panic!("Out of order timestamp: {} after {}", action.timestamp, previous);
+                    }
+                    self.out_of_order += 1;
                 }
-                self.last_timestamp = Some(action.timestamp);
-                return Some((action, &self.process_state, self.first_timestamp));
             }
+            self.previous_timestamp = Some(action.timestamp);
+            return Some((action, &self.process_state, self.start_timestamp));
+        }
+    }
+
+    // cpu whose runqueue the task is on, None if it is not runnable
+    pub fn task_cpu(&self, pid: u32) -> Option<u32> {
+        self.runqueue.get(&pid).copied()
+    }
+
+    // number of runnable tasks on a cpu, including the one running
+    pub fn runqueue_depth(&self, cpu: u32) -> u32 {
+        self.runqueue_depth.get(cpu as usize).copied().unwrap_or(0)
+    }
+
+    // move a task to the runqueue of cpu, or take it off the runqueues if cpu is None
+    fn enqueue(&mut self, pid: u32, cpu: Option<u32>) {
+        // the idle task is never counted
+        if pid == 0 {
+            return;
+        }
+        // cpus past the cpus= header, like hotplugged ones, get a runqueue when first seen
+        if let Some(cpu) = cpu.filter(|cpu| *cpu as usize >= self.runqueue_depth.len()) {
+            self.runqueue_depth.resize(cpu as usize + 1, 0);
+        }
+        let old_cpu = match cpu {
+            Some(cpu) => self.runqueue.insert(pid, cpu),
+            None => self.runqueue.remove(&pid),
+        };
+        if let Some(depth) = old_cpu.and_then(|old_cpu| self.runqueue_depth.get_mut(old_cpu as usize)) {
+            *depth -= 1;
+        }
+        if let Some(depth) = cpu.and_then(|cpu| self.runqueue_depth.get_mut(cpu as usize)) {
+            *depth += 1;
+        }
+    }
+
+    // read the rest of the trace for its smallest and largest timestamps only, much faster than next_action
+    pub fn scan_timestamps(&mut self) {
+        while let Some(line) = self.next_line() {
+            self.lines_read += 1;
+            let Some(timestamp) = line_timestamp(&line) else {
+                continue;
+            };
+            self.first_timestamp = Some(self.first_timestamp.map_or(timestamp, |first| first.min(timestamp)));
+            self.last_timestamp = Some(self.last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
+        }
+    }
+
+    // next line of the trace without its line break, None at the end of the file or on a read error
+    fn next_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(read) => {
+                self.position += read as u64;
+                line.truncate(line.trim_end_matches(['\n', '\r']).len());
+                Some(line)
+            }
+        }
+    }
+
+    fn seek(&mut self, position: u64) {
+        self.reader.seek(SeekFrom::Start(position)).expect("Failed to seek in trace");
+        self.position = position;
+    }
+
+    // Only regular files can be read from an offset, pipes are read line by line
+    fn seekable(&self) -> bool {
+        self.reader.get_ref().metadata().is_ok_and(|metadata| metadata.is_file())
+    }
+
+    // first event timestamp from the next line, which is not consumed, (start of the line, timestamp)
+    fn peek_timestamp(&mut self) -> Option<(u64, f64)> {
+        let position = self.position;
+        let mut found = None;
+        loop {
+            let start = self.position;
+            let Some(line) = self.next_line() else {
+                break;
+            };
+            if let Some(timestamp) = line_timestamp(&line) {
+                found = Some((start, timestamp));
+                break;
+            }
+        }
+        self.seek(position);
+        found
+    }
+
+    // The origin of the plot, read ahead from the first event if no line was read yet
+    // None if it is unknown and the trace cannot be read ahead
+    pub fn read_start_timestamp(&mut self) -> Option<f64> {
+        if self.start_timestamp.is_none() && self.seekable() {
+            if let Some((_, timestamp)) = self.peek_timestamp() {
+                self.start_timestamp = Some(timestamp);
+                self.first_timestamp = Some(timestamp);
+                self.last_timestamp = Some(timestamp);
+                self.first_event_timestamp.get_or_insert(timestamp);
+            }
+        }
+        self.start_timestamp
+    }
+
+    // Move to a line before the first event at or after timestamp, by a binary search on the byte offsets
+    // Never moves past that event, but assumes the timestamps increase through the file
+    // The waking states and runqueues of the skipped lines are lost, false if the trace cannot seek
+    pub fn seek_to_timestamp(&mut self, timestamp: f64) -> bool {
+        if !self.seekable() {
+            return false;
+        }
+        let end = self.reader.get_ref().metadata().map(|metadata| metadata.len()).unwrap_or(self.position);
+        let start = self.position;
+        // low is the start of a line before the timestamp, or the current line, the first event after high is not before it
+        let (mut low, mut high) = (self.position, end);
+        while high - low > SEEK_PRECISION {
+            let middle = low + (high - low) / 2;
+            self.seek(middle);
+            // the line cut by the offset is skipped
+            let probe = self.next_line().and_then(|_| self.peek_timestamp());
+            match probe {
+                Some((line, time)) if time < timestamp && line < high => low = line,
+                _ => high = middle,
+            }
+        }
+        self.seek(low);
+        if low > start {
+            self.previous_timestamp = None;
+        }
+        true
+    }
+
+    // header of another report concatenated to the trace, the tasks of the previous one are forgotten
+    // The cpu count of the first header is kept for the plot, the runqueues fit the largest one
+    fn start_session(&mut self, cpus: &str) {
+        self.sessions += 1;
+        self.process_state.clear();
+        self.runqueue.clear();
+        let cpu_count = cpus.parse().unwrap_or(self.cpu_count).max(self.runqueue_depth.len() as u32);
+        self.runqueue_depth = vec![0; cpu_count as usize];
+        // timestamps start over, which is not an out of order event
+        self.previous_timestamp = None;
+    }
+
+    // The depth follows the task and not the cpu of the event:
+    // a task woken on a cpu is queued on the target cpu and moves with its migrations
+    fn update_runqueue(&mut self, action: &Action) {
+        match &action.event {
+            Events::SchedWakeup { pid, cpu, .. } | Events::SchedWakeupNew { pid, cpu, .. } => {
+                self.enqueue(*pid, Some(*cpu));
+            }
+            Events::SchedMigrateTask { pid, dest_cpu, .. } if self.runqueue.contains_key(pid) => {
+                self.enqueue(*pid, Some(*dest_cpu));
+            }
+            Events::SchedSwitch { old_pid, state, new_pid, .. } => {
+                // a preempted task stays runnable, otherwise it leaves the runqueue
+                let old_cpu = if classify_state(state, &self.task_states) == TaskState::Running { Some(action.cpu) } else { None };
+                self.enqueue(*old_pid, old_cpu);
+                // the new task runs here, even if its wakeup or migration was not traced
+                self.enqueue(*new_pid, Some(action.cpu));
+            }
+            _ => {}
         }
-        None
     }
+}
 
+// Whether the field at index can hold the pid of a "command<sep>pid" token: the pid is followed by a bracketed field,
+// the cpu or the priority, by a cpu= field, or by the ==> of sched_switch or the state right before it when there is no priority,
+// so commands like kworker/0:1-events or my-app-2 are not cut at their own separator
+fn followed_by_field(parts: &[&str], index: usize) -> bool {
+    parts.get(index + 1).is_none_or(|next| next.starts_with('[') || next.starts_with("cpu=") || *next == "==>")
+        || parts.get(index + 2) == Some(&"==>")
 }
 
-fn extract_command_and_pid(parts: &[&str], sep: char, n: usize) -> (String, u32, usize) {
-    let mut command = String::new();
-    let mut pid = 0;
-    let mut next_index = n;
+// Readers of the fields of a line, which copy the fields they read
+// Both sets are built for the tests, to check they read the fixtures the same
+#[cfg(any(test, not(feature = "fast-parse")))]
+mod copied {
+    use std::str::FromStr;
+    use super::followed_by_field;
+
+    // Command and pid from a "command<sep>pid" token, the command may contain spaces and the separator
+    // The pid is the last <sep><digits> of a field followed by another field, see followed_by_field
+    // None if no token has a pid
+    pub fn extract_command_and_pid(parts: &[&str], sep: char, n: usize) -> Option<(String, u32, usize)> {
+        let mut command = String::new();
 
-    for (index, part) in parts.iter().skip(n).enumerate() {
+        for (index, part) in parts.iter().enumerate().skip(n) {
+            if index != n {
+                command.push(' ');
+            }
             if let Some((base, suffix)) = part.rsplit_once(sep) {
-                if let Ok(p) = suffix.parse::<u32>() {
-                    if index != 0 {
-                        command.push(' ');
-                    }
+                if let (true, Ok(pid)) = (followed_by_field(parts, index), suffix.parse::<u32>()) {
                     command.push_str(base);
-                    pid = p;
-                    next_index += index;
-                    break;
+                    return Some((command, pid, index));
                 }
+            }
+            command.push_str(part);
+        }
+        None
+    }
+
+    // a command written over several fields, joined by spaces
+    pub fn join_command(first: &str, rest: &[&str]) -> String {
+        std::iter::once(first).chain(rest.iter().copied()).collect::<Vec<&str>>().join(" ")
+    }
+
+    // Value of the field at index, with its name= prefix removed, None if the field is missing or not a value of type T
+    pub fn named_field<T: FromStr>(part: &[&str], index: usize, name: &str) -> Option<T> {
+        part.get(index)?.replace(name, "").parse().ok()
+    }
+}
+
+// Readers of the fast-parse feature, which read the fields in place and only allocate the command they return
+// The name of a field is only removed at its start, where it always is in a well-formed trace
+#[cfg(any(test, feature = "fast-parse"))]
+mod sliced {
+    use std::str::FromStr;
+    use super::followed_by_field;
+
+    // the pid is found first, so the command is built once at its length
+    pub fn extract_command_and_pid(parts: &[&str], sep: char, n: usize) -> Option<(String, u32, usize)> {
+        let (index, base, pid) = (n..parts.len()).find_map(|index| {
+            let (base, suffix) = parts[index].rsplit_once(sep)?;
+            let pid = suffix.parse::<u32>().ok().filter(|_| followed_by_field(parts, index))?;
+            Some((index, base, pid))
+        })?;
+        let mut command = String::with_capacity(parts[n..index].iter().map(|part| part.len() + 1).sum::<usize>() + base.len());
+        for part in &parts[n..index] {
+            command.push_str(part);
+            command.push(' ');
         }
+        command.push_str(base);
+        Some((command, pid, index))
+    }
+
+    pub fn join_command(first: &str, rest: &[&str]) -> String {
+        let mut command = String::with_capacity(first.len() + rest.iter().map(|part| part.len() + 1).sum::<usize>());
+        command.push_str(first);
+        for part in rest {
             command.push(' ');
             command.push_str(part);
         }
-    (command, pid, next_index)
+        command
+    }
+
+    pub fn named_field<T: FromStr>(part: &[&str], index: usize, name: &str) -> Option<T> {
+        let field = part.get(index)?;
+        field.strip_prefix(name).unwrap_or(field).parse().ok()
+    }
 }
 
-fn parse_named_args(parts: &[&str], position: usize, comm: &str, id: &str) -> (String, u32, usize) {
-    let mut command = String::new();
-    command.push_str(parts[position].replace(comm, "").as_str());
-    let mut position = position + 1;
+#[cfg(not(feature = "fast-parse"))]
+use copied::{ extract_command_and_pid, join_command, named_field };
+#[cfg(feature = "fast-parse")]
+use sliced::{ extract_command_and_pid, join_command, named_field };
 
-    for (index, part) in parts.iter().skip(position).enumerate() {
-        if part.starts_with(id) { 
-            position += index;
-            break; 
-        }
-        command.push(' ');
-        command.push_str(part);
+// Command and pid of "comm=<command> pid=<pid> <next>..." fields, next being the field after the pid, None if it is the last one
+// The command may contain spaces, '=' or even a pid= token, so the pid is the id field directly followed by next
+// If the line has other fields than expected, the first id field with a number is used
+fn parse_named_args(parts: &[&str], position: usize, comm: &str, id: &str, next: Option<&str>) -> Option<(String, u32, usize)> {
+    let is_id = |index: usize| parts[index].strip_prefix(id).is_some_and(|pid| pid.parse::<u32>().is_ok());
+    let followed_by_next = |index: usize| match (next, parts.get(index + 1)) {
+        (Some(next), Some(part)) => part.starts_with(next),
+        (None, None) => true,
+        _ => false,
+    };
+    let id_position = (position + 1..parts.len()).find(|index| is_id(*index) && followed_by_next(*index))
+                        .or_else(|| (position + 1..parts.len()).find(|index| is_id(*index)))?;
+
+    let first = parts[position].strip_prefix(comm).unwrap_or(parts[position]);
+    let command = join_command(first, &parts[position + 1..id_position]);
+    let pid: u32 = parts[id_position][id.len()..].parse().ok()?;
+    Some((command, pid, id_position))
+}
+
+// Value of the first name= field after index, for fields whose position differs between kernels,
+// like the ones after an optional prio= field
+fn field_after<T: FromStr>(part: &[&str], index: usize, name: &str) -> Option<T> {
+    part.get(index + 1..)?.iter().find_map(|field| field.strip_prefix(name))?.parse().ok()
+}
+
+// Command, pid and target cpu of sched_wakeup and sched_wakeup_new, in either format:
+// "command:pid [prio] CPU:cpu" or the legacy "comm=command pid=pid prio=prio success=1 target_cpu=cpu" of older kernels
+fn wakeup_fields(part: &[&str], index: usize) -> Option<(String, u32, u32)> {
+    if part.get(index)?.starts_with("comm=") {
+        let (command, pid, index) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
+        let cpu = field_after(part, index, "target_cpu=")?;
+        Some((command, pid, cpu))
+    } else {
+        let (command, pid, index) = extract_command_and_pid(part, ':', index)?;
+        let cpu: u32 = named_field(part, index + 2, "CPU:")?;
+        Some((command, pid, cpu))
     }
+}
 
-    let pid: u32 = parts[position].replace(id, "").parse().unwrap();
-    (command, pid, position)
+// A task of sched_switch with its priority, which is not written by every kernel
+type SwitchTask = (String, u32, Option<u32>);
+
+// Previous task, its state and next task of sched_switch, read on both sides of the ==> separator, in either format:
+// "command:pid [prio] state ==> command:pid [prio]" or the named
+// "prev_comm=command prev_pid=pid prev_prio=prio prev_state=state ==> next_comm=command next_pid=pid next_prio=prio"
+// The separator is searched after the previous pid, so a command containing ==> does not split the line
+fn switch_fields(part: &[&str], index: usize) -> Option<(SwitchTask, String, SwitchTask)> {
+    let arrow_after = |pid_index: usize| part.iter().skip(pid_index + 1).position(|field| *field == "==>").map(|position| pid_index + 1 + position);
+    if part.get(index)?.starts_with("prev_comm=") {
+        let (old_command, old_pid, pid_index) = parse_named_args(part, index, "prev_comm=", "prev_pid=", Some("prev_"))?;
+        let arrow = arrow_after(pid_index)?;
+        let old_prio = part[pid_index + 1..arrow].iter().find_map(|field| field.strip_prefix("prev_prio=")?.parse().ok());
+        let state = part[pid_index + 1..arrow].iter().find_map(|field| field.strip_prefix("prev_state="))?;
+        let (new_command, new_pid, pid_index) = parse_named_args(part, arrow + 1, "next_comm=", "next_pid=", Some("next_prio="))?;
+        let new_prio = field_after(part, pid_index, "next_prio=");
+        Some(((old_command, old_pid, old_prio), state.to_string(), (new_command, new_pid, new_prio)))
+    } else {
+        let (old_command, old_pid, pid_index) = extract_command_and_pid(part, ':', index)?;
+        let arrow = arrow_after(pid_index)?;
+        let old_prio = part.get(pid_index + 1).and_then(|field| parse_prio_field(field));
+        // the state is the field right before the separator, after the prio
+        let state = part[pid_index + 1..arrow].last().filter(|field| parse_prio_field(field).is_none())?;
+        let (new_command, new_pid, pid_index) = extract_command_and_pid(part, ':', arrow + 1)?;
+        let new_prio = part.get(pid_index + 1).and_then(|field| parse_prio_field(field));
+        Some(((old_command, old_pid, old_prio), state.to_string(), (new_command, new_pid, new_prio)))
+    }
 }
 
+// move the waking state of pid along the event, returning the state it was in
+fn update_state(process_state: &mut HashMap<u32, Wstate>, pid: u32, event: RawEvent) -> Option<Wstate> {
+    let current = process_state.get(&pid).copied();
+    process_state.insert(pid, transition(current, &event));
+    current
+}
 
 // parse and return the event's information as a struct with the type Events
-fn get_event(part: &Vec<&str>, _process_pid: u32, process_cpu: u32, process_state: &mut HashMap<u32, Wstate>, event_type: &str, index: usize) -> Events {
-    match event_type {
+// None if a field is missing or cannot be read, like in a line cut short at the end of the trace
+// The fields are all read before the waking states are updated, so such a line leaves them as they were
+fn get_event(part: &Vec<&str>, _process_pid: u32, process_cpu: u32, timestamp: f64, process_state: &mut HashMap<u32, Wstate>, event_type: &str, index: usize) -> Option<Events> {
+    let event = match event_type {
         "sched_waking" => {
-            let (command, pid, index) = parse_named_args(&part, index, "comm=", "pid=");
-            let target_cpu: u32 = String::from(part[index + 2]).replace("target_cpu=", "").parse().unwrap();
+            let (command, pid, index) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
+            let target_cpu: u32 = named_field(part, index + 2, "target_cpu=")?;
 
-            process_state.insert(pid, Wstate::Waking(process_cpu, target_cpu));
+            update_state(process_state, pid, RawEvent::Waking { waker_cpu: process_cpu, target_cpu, timestamp });
             Events::SchedWaking { command, pid, target_cpu }
         }
         "sched_wake_idle_without_ipi" => {
-            let cpu = String::from(part[index]).replace("cpu=", "").parse().unwrap();
+            let cpu = named_field(part, index, "cpu=")?;
             Events::SchedWakeIdleNoIpi { cpu }
         }
         "sched_wakeup" => {
-            let (command, pid, index) = extract_command_and_pid(part, ':', index);
-            let cpu: u32 = String::from(part[index + 2]).replace("CPU:", "").parse().unwrap();
+            let (command, pid, cpu) = wakeup_fields(part, index)?;
 
-            let mut prev_cpu: Option<u32> = None;
-            if process_state.contains_key(&pid) {
-                if let Wstate::Waking(old_cpu, _) = process_state[&pid] {
-                    prev_cpu = Some(old_cpu);
-                }
-            }
-            process_state.insert(pid, Wstate::Woken);
+            let prev_cpu = match update_state(process_state, pid, RawEvent::Wakeup) {
+                Some(Wstate::Waking(old_cpu, ..)) => Some(old_cpu),
+                _ => None,
+            };
             Events::SchedWakeup { command, pid, prev_cpu, cpu }
         }
         "sched_wakeup_new" => {
-            let (command, pid, index) = extract_command_and_pid(part, ':', index);
-            let cpu: u32 = String::from(part[index + 2]).replace("CPU:", "").parse().unwrap();
-
-            let mut parent_cpu = cpu;
-            if process_state.contains_key(&pid) {
-                if let Wstate::Waking(_, parent) = process_state[&pid] {
-                    parent_cpu = parent;
-                } 
-                else {
-                    panic!("Wakeup without fork");
-                }
-            }
-            process_state.insert(pid, Wstate::Woken);
-            Events::SchedWakeupNew { command, pid, parent_cpu, cpu }
+            let (command, pid, cpu) = wakeup_fields(part, index)?;
+
+            // the fork may have happened before the trace started
+            let (parent_cpu, forked) = match update_state(process_state, pid, RawEvent::Wakeup) {
+                Some(Wstate::Waking(_, parent, _)) => (parent, true),
+                _ => (cpu, false),
+            };
+            Events::SchedWakeupNew { command, pid, parent_cpu, cpu, forked }
         }
         "sched_migrate_task" => {
-            let (command, pid, index) = parse_named_args(&part, index, "comm=", "pid=");
-            let orig_cpu: u32 = String::from(part[index + 2]).replace("orig_cpu=", "").parse().unwrap();
-            let dest_cpu: u32 = String::from(part[index + 3]).replace("dest_cpu=", "").parse().unwrap();
+            let (command, pid, index) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
+            let orig_cpu: u32 = field_after(part, index, "orig_cpu=")?;
+            let dest_cpu: u32 = field_after(part, index, "dest_cpu=")?;
 
-            let mut temp = Wstate::Woken;
-            if process_state.contains_key(&pid) {
-                temp = process_state[&pid];
-            }
-            let mut state = temp;
-            if let Wstate::Numa(c1, c2) = temp {
-                if orig_cpu != c1 as u32 || dest_cpu != c2 as u32 {
-                    state = Wstate::Woken;
-                }
+            // tasks without a state are not tracked, migrations only classify them
+            let current = process_state.get(&pid).copied();
+            let state = transition(current, &RawEvent::Migrate { orig_cpu, dest_cpu });
+            if current.is_some() {
+                process_state.insert(pid, state);
             }
             Events::SchedMigrateTask { command, pid, orig_cpu, dest_cpu, state}
         }
         "sched_switch" => {
-            let (old_command, old_pid, index) = extract_command_and_pid(part, ':', index);
-            let state = part[index + 2];
-            let (new_command, new_pid, ..) = extract_command_and_pid(part, ':', index + 4);
-            
-            Events::SchedSwitch { old_command, old_pid, state: String::from(state), new_command, new_pid }
+            let ((old_command, old_pid, old_prio), state, (new_command, new_pid, new_prio)) = switch_fields(part, index)?;
+
+            Events::SchedSwitch { old_command, old_pid, old_prio, state, new_command, new_pid, new_prio }
         },
         "sched_process_free" => {
-            let (command, pid, ..) = parse_named_args(&part, index, "comm=", "pid=");
+            let (command, pid, ..) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
 
             Events::SchedProcessFree { command, pid }
         },
         "sched_process_exec" => {
-            let index = index;
-            let filename = String::from(part[index]).replace("filename=", "");
-            let pid: u32 = String::from(part[index + 1]).replace("pid=", "").parse().unwrap();
-            let old_pid: u32 = String::from(part[index + 2]).replace("old_pid=", "").parse().unwrap();
+            // the filename may contain spaces, so read up to the pid field
+            let (filename, pid, index) = parse_named_args(part, index, "filename=", "pid=", Some("old_pid="))?;
+            let old_pid: u32 = named_field(part, index + 1, "old_pid=")?;
             Events::SchedProcessExec { filename, pid, old_pid }
         },
         "sched_process_fork" => {
-            let (command, pid, index) = parse_named_args(&part, index, "comm=", "pid=");
-            let (child_command, child_pid, ..) = parse_named_args(&part, index + 1, "child_comm=", "child_pid=");
+            let (command, pid, index) = parse_named_args(part, index, "comm=", "pid=", Some("child_comm="))?;
+            let (child_command, child_pid, ..) = parse_named_args(part, index + 1, "child_comm=", "child_pid=", None)?;
 
-            process_state.insert(child_pid, Wstate::Waking(process_cpu, process_cpu));
+            update_state(process_state, child_pid, RawEvent::Fork { parent_cpu: process_cpu, timestamp });
             Events::SchedProcessFork { command, pid, child_command, child_pid }
         },
         "sched_process_wait" => {
-            let (command, pid, ..) = parse_named_args(&part, index, "comm=", "pid=");
+            let (command, pid, ..) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
             Events::SchedProcessWait { command, pid }
         },
         "sched_process_exit" => {
-            let (command, pid, ..) = parse_named_args(&part, index, "comm=", "pid=");
+            let (command, pid, ..) = parse_named_args(part, index, "comm=", "pid=", Some("prio="))?;
             Events::SchedProcessExit { command, pid }
         },
         "sched_swap_numa" => {
-            let src_pid: u32 = String::from(part[index]).replace("src_pid=", "").parse().unwrap();
-            let src_cpu: i32 = String::from(part[index + 3]).replace("src_cpu=", "").parse().unwrap();
+            let src_pid: u32 = named_field(part, index, "src_pid=")?;
+            let src_cpu: i32 = named_field(part, index + 3, "src_cpu=")?;
 
-            let dst_pid: u32 = String::from(part[index + 5]).replace("dst_pid=", "").parse().unwrap();
-            let dst_cpu: i32 = String::from(part[index + 8]).replace("dst_cpu=", "").parse().unwrap();
+            let dst_pid: u32 = named_field(part, index + 5, "dst_pid=")?;
+            let dst_cpu: i32 = named_field(part, index + 8, "dst_cpu=")?;
 
-            process_state.insert(src_pid, Wstate::Numa(src_cpu, dst_cpu));
-            process_state.insert(dst_pid, Wstate::Numa(dst_cpu, src_cpu));
+            update_state(process_state, src_pid, RawEvent::Numa { src_cpu, dst_cpu });
+            update_state(process_state, dst_pid, RawEvent::Numa { src_cpu: dst_cpu, dst_cpu: src_cpu });
             Events::SchedSwapNuma { src_pid, src_cpu, dst_pid, dst_cpu }
         }
         "sched_stick_numa" => {
-            let src_pid: u32 = String::from(part[index]).replace("src_pid=", "").parse().unwrap();
-            let src_cpu: i32 = String::from(part[index + 3]).replace("src_cpu=", "").parse().unwrap();
+            let src_pid: u32 = named_field(part, index, "src_pid=")?;
+            let src_cpu: i32 = named_field(part, index + 3, "src_cpu=")?;
 
-            let dst_pid: u32 = String::from(part[index + 5]).replace("dst_pid=", "").parse().unwrap();
-            let dst_cpu: i32 = String::from(part[index + 8]).replace("dst_cpu=", "").parse().unwrap();
+            let dst_pid: u32 = named_field(part, index + 5, "dst_pid=")?;
+            let dst_cpu: i32 = named_field(part, index + 8, "dst_cpu=")?;
 
             Events::SchedStickNuma { src_pid, src_cpu, dst_pid, dst_cpu }
         },
         "sched_move_numa" => {
-            let src_pid: u32 = String::from(part[index]).replace("pid=", "").parse().unwrap();
-            let src_cpu: i32 = String::from(part[index + 3]).replace("src_cpu=", "").parse().unwrap();
-            let dst_cpu: i32 = String::from(part[index + 5]).replace("dst_cpu=", "").parse().unwrap();
+            let src_pid: u32 = named_field(part, index, "pid=")?;
+            let src_cpu: i32 = named_field(part, index + 3, "src_cpu=")?;
+            let dst_cpu: i32 = named_field(part, index + 5, "dst_cpu=")?;
 
-            process_state.insert(src_pid, Wstate::Numa(src_cpu, dst_cpu));
+            update_state(process_state, src_pid, RawEvent::Numa { src_cpu, dst_cpu });
             Events::SchedMoveNuma { src_pid, src_cpu, dst_cpu }
         }
-        _ => Events::NotSupported
+        "irq_handler_entry" => {
+            let irq: u32 = named_field(part, index, "irq=")?;
+            let name = part[index + 1..].join(" ").replace("name=", "");
+            Events::IrqHandlerEntry { irq, name, cpu: process_cpu }
+        }
+        "softirq_entry" => {
+            let vec: u32 = named_field(part, index, "vec=")?;
+            // written as [action=NET_RX]
+            let action = part.get(index + 1)?.trim_start_matches("[action=").trim_end_matches(']').to_string();
+            Events::SoftirqEntry { vec, action, cpu: process_cpu }
+        }
+        "sched_pi_setprio" => {
+            let (command, pid, index) = parse_named_args(part, index, "comm=", "pid=", Some("oldprio="))?;
+            let old_prio: i32 = named_field(part, index + 1, "oldprio=")?;
+            let new_prio: i32 = named_field(part, index + 2, "newprio=")?;
+            Events::SchedPiSetprio { command, pid, old_prio, new_prio }
+        }
+        "cpu_idle" => {
+            let state: u32 = named_field(part, index, "state=")?;
+            let cpu: u32 = named_field(part, index + 1, "cpu_id=")?;
+            Events::CpuIdle { state, cpu }
+        }
+        _ => Events::NotSupported(event_type.to_string())
+    };
+    Some(event)
+}
+
+// Cpu count of the cpus=<n> token of a header line, which can be preceded by a comment or followed by (online)
+// None if the line has no such token
+fn header_cpu_count(line: &str) -> Option<Result<u32, String>> {
+    let token = line.split_whitespace().find(|token| token.contains("cpus="))?;
+    let count = token.split("cpus=").last().unwrap_or_default().trim_end_matches(|c: char| !c.is_ascii_digit());
+    Some(count.parse().map_err(|_| format!("Invalid cpu count: {}", token)))
+}
+
+// timestamp of an event line, None for lines that are too short to be one
+fn line_timestamp(line: &str) -> Option<f64> {
+    let part: Vec<&str> = line.split_whitespace().collect();
+    if part.len() <= 2 || part[0].starts_with("cpus=") {
+        return None;
+    }
+    let (_, _, index) = extract_command_and_pid(&part, '-', 0)?;
+    find_timestamp(&part, index + 2).map(|(timestamp, _)| timestamp)
+}
+
+// cpu and timestamp of an event line, None for lines that are not events
+pub fn line_cpu_and_timestamp(line: &str) -> Option<(u32, f64)> {
+    let part: Vec<&str> = line.split_whitespace().collect();
+    if part.len() <= 2 || part[0].starts_with("cpus=") {
+        return None;
+    }
+    let (_, _, index) = extract_command_and_pid(&part, '-', 0)?;
+    let cpu = parse_cpu_field(part.get(index + 1)?)?;
+    find_timestamp(&part, index + 2).map(|(timestamp, _)| (cpu, timestamp))
+}
+
+// cpu column, written as [001] or [1] depending on the trace-cmd version,
+// or as cpu=1 in traces converted from perf and some ftrace variants
+fn parse_cpu_field(field: &str) -> Option<u32> {
+    match field.strip_prefix("cpu=") {
+        Some(cpu) => cpu.parse().ok(),
+        None => field.trim_start_matches('[').trim_end_matches(']').parse().ok(),
+    }
+}
+
+// priority of a task, written as [120] after its pid
+fn parse_prio_field(field: &str) -> Option<u32> {
+    field.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+// timestamp column, the trailing ':' is not emitted by every trace-cmd version
+fn parse_timestamp_field(field: &str) -> Option<f64> {
+    field.strip_suffix(':').unwrap_or(field).parse().ok()
+}
+
+// the timestamp comes after the cpu column, or after the optional latency flags column (d.h., ....)
+// None if there is none, like in a line cut short at the end of the trace
+fn find_timestamp(part: &[&str], index: usize) -> Option<(f64, usize)> {
+    part.iter().enumerate().skip(index).take(2)
+        .find_map(|(position, field)| parse_timestamp_field(field).map(|timestamp| (timestamp, position)))
+}
+
+// parse a line of the trace, None if it has too few fields to be an event or cannot be parsed
+// process_state carries the waking states from one line to the next
+pub fn parse_line(line: &str, process_state: &mut HashMap<u32, Wstate>) -> Option<Action> {
+    let part: Vec<&str> = line.split_whitespace().collect();
+    if part.len() > 2 {
+        get_action(&part, process_state)
+    } else {
+        None
     }
 }
 
-// parse and return the action
-pub fn get_action(part: &Vec<&str>, process_state: &mut HashMap<u32, Wstate>) -> Action {
-    let (process, pid, index) = extract_command_and_pid(part, '-', 0);
-    let cpu: u32 = String::from(part[index + 1]).replace(&['[', ']'][..], "").parse().unwrap();
-
-    let mut timestamp = String::from(part[index + 2]);
-    timestamp.pop();
-    let timestamp: f64 = timestamp.parse().unwrap();
-    
-    let mut event_type = String::from(part[index + 3]);
-    event_type.pop();
-    
-    let event = get_event(part, pid, cpu, process_state, &event_type, index + 4);
-    Action {process, pid, cpu, timestamp, event}
+// parse and return the action, None if the line is malformed
+pub fn get_action(part: &Vec<&str>, process_state: &mut HashMap<u32, Wstate>) -> Option<Action> {
+    let (process, pid, index) = extract_command_and_pid(part, '-', 0)?;
+    let cpu = parse_cpu_field(part.get(index + 1)?)?;
+    let (timestamp, index) = find_timestamp(part, index + 2)?;
+    let event_type = part.get(index + 1)?;
+    let event_type = event_type.strip_suffix(':').unwrap_or(event_type);
+
+    let event = get_event(part, pid, cpu, timestamp, process_state, event_type, index + 2)?;
+    Some(Action {process, pid, cpu, timestamp, event})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action_from(line: &str) -> Action {
+        let part: Vec<&str> = line.split_whitespace().collect();
+        get_action(&part, &mut HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn copied_and_sliced_fields_read_the_fixtures_the_same() {
+        let names = ["CPU:", "target_cpu=", "cpu=", "old_pid=", "src_pid=", "src_cpu=", "dst_pid=", "dst_cpu=", "pid=", "irq=", "vec=", "oldprio=", "newprio=", "state=", "cpu_id="];
+        let fixtures = std::fs::read_dir(format!("{}/tests/fixtures", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        for fixture in fixtures {
+            for line in std::fs::read_to_string(fixture.unwrap().path()).unwrap().lines() {
+                let part: Vec<&str> = line.split_whitespace().collect();
+                for index in 0..=part.len() {
+                    for sep in ['-', ':'] {
+                        assert_eq!(copied::extract_command_and_pid(&part, sep, index), sliced::extract_command_and_pid(&part, sep, index), "{}", line);
+                    }
+                    for name in names {
+                        assert_eq!(copied::named_field::<u32>(&part, index, name), sliced::named_field::<u32>(&part, index, name), "{}", line);
+                        assert_eq!(copied::named_field::<i32>(&part, index, name), sliced::named_field::<i32>(&part, index, name), "{}", line);
+                    }
+                    if let Some((first, rest)) = part[index..].split_first() {
+                        assert_eq!(copied::join_command(first, rest), sliced::join_command(first, rest));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_field_with_and_without_padding() {
+        assert_eq!(parse_cpu_field("[001]"), Some(1));
+        assert_eq!(parse_cpu_field("[1]"), Some(1));
+        assert_eq!(parse_cpu_field("[120]"), Some(120));
+        assert_eq!(parse_cpu_field("cpu=1"), Some(1));
+        assert_eq!(parse_cpu_field("cpu=001"), Some(1));
+        assert_eq!(parse_cpu_field("cpu=[1]"), None);
+    }
+
+    #[test]
+    fn timestamp_with_and_without_colon() {
+        assert_eq!(parse_timestamp_field("100.000250:"), Some(100.000250));
+        assert_eq!(parse_timestamp_field("100.000250"), Some(100.000250));
+    }
+
+    #[test]
+    fn cpu_and_timestamp_of_event_lines_only() {
+        assert_eq!(line_cpu_and_timestamp("  my-app-2-1600  [003] d..2.  100.000400: sched_switch: my-app-2:1600 [120] R ==> swapper/3:0 [120]"), Some((3, 100.000400)));
+        assert_eq!(line_cpu_and_timestamp("cpus=4"), None);
+        assert_eq!(line_cpu_and_timestamp("CPU 0 is empty"), None);
+    }
+
+    #[test]
+    fn action_with_padded_cpu_and_colon() {
+        let action = action_from("bash-1000  [001]  100.000250: sched_waking: comm=bash pid=1001 prio=120 target_cpu=002");
+        assert_eq!(action.cpu, 1);
+        assert_eq!(action.timestamp, 100.000250);
+        assert!(matches!(action.event, Events::SchedWaking { pid: 1001, target_cpu: 2, .. }));
+    }
+
+    #[test]
+    fn exec_filename_with_spaces() {
+        let action = action_from("bash-1001  [001]  100.000500: sched_process_exec: filename=/opt/my app/run pid=1001 old_pid=1001");
+        assert!(matches!(action.event,
+            Events::SchedProcessExec { ref filename, pid: 1001, old_pid: 1001 } if filename == "/opt/my app/run"));
+    }
+
+    #[test]
+    fn action_with_short_cpu_and_no_colon() {
+        let action = action_from("bash-1000  [1]  100.000250 sched_waking comm=bash pid=1001 prio=120 target_cpu=002");
+        assert_eq!(action.process, "bash");
+        assert_eq!(action.pid, 1000);
+        assert_eq!(action.cpu, 1);
+        assert_eq!(action.timestamp, 100.000250);
+        assert!(matches!(action.event, Events::SchedWaking { pid: 1001, target_cpu: 2, .. }));
+    }
+    #[test]
+    fn action_with_latency_column() {
+        let action = action_from("bash-1000  [000] d.h.  100.000300: sched_wakeup_new: bash:1001 [120] CPU:001");
+        assert_eq!(action.cpu, 0);
+        assert_eq!(action.timestamp, 100.000300);
+        assert!(matches!(action.event, Events::SchedWakeupNew { pid: 1001, cpu: 1, .. }));
+
+        let action = action_from("<idle>-0  [002] ....  100.000800: sched_switch: swapper/2:0 [120] R ==> kworker/0:1:50 [120]");
+        assert_eq!(action.timestamp, 100.000800);
+        assert!(matches!(action.event, Events::SchedSwitch { old_pid: 0, new_pid: 50, .. }));
+    }
+
+    #[test]
+    fn pid_is_the_last_number_before_the_cpu() {
+        let action = action_from("kworker/0:1-289  [000]  100.000100: sched_waking: comm=bash pid=1001 prio=120 target_cpu=000");
+        assert_eq!(action.process, "kworker/0:1");
+        assert_eq!(action.pid, 289);
+
+        let action = action_from("migration/5-42  [005]  100.000100: sched_waking: comm=bash pid=1001 prio=120 target_cpu=000");
+        assert_eq!(action.process, "migration/5");
+        assert_eq!(action.pid, 42);
+
+        let action = action_from("my-app-2-1500  [001]  100.000100: sched_waking: comm=bash pid=1001 prio=120 target_cpu=000");
+        assert_eq!(action.process, "my-app-2");
+        assert_eq!(action.pid, 1500);
+
+        let action = action_from("web-2 worker-3-1600  [001]  100.000100: sched_waking: comm=bash pid=1001 prio=120 target_cpu=000");
+        assert_eq!(action.process, "web-2 worker-3");
+        assert_eq!(action.pid, 1600);
+        assert_eq!(action.cpu, 1);
+    }
+
+    #[test]
+    fn named_command_with_equals_and_digits() {
+        let action = action_from("bash-1000  [001]  100.000250: sched_waking: comm=foo=bar pid=1001 prio=120 target_cpu=002");
+        assert!(matches!(action.event, Events::SchedWaking { ref command, pid: 1001, target_cpu: 2 } if command == "foo=bar"));
+
+        let action = action_from("bash-1000  [001]  100.000250: sched_waking: comm=runc pid=12 init pid=1001 prio=120 target_cpu=002");
+        assert!(matches!(action.event, Events::SchedWaking { ref command, pid: 1001, target_cpu: 2 } if command == "runc pid=12 init"));
+
+        let action = action_from("bash-1000  [000]  100.001200: sched_migrate_task: comm=worker 12 pid=1000 prio=120 orig_cpu=0 dest_cpu=3");
+        assert!(matches!(action.event,
+            Events::SchedMigrateTask { ref command, pid: 1000, orig_cpu: 0, dest_cpu: 3, .. } if command == "worker 12"));
+    }
+
+    #[test]
+    fn fork_with_named_commands() {
+        let action = action_from("bash-1000  [000]  100.000200: sched_process_fork: comm=a=b pid=1000 child_comm=c child_pid=7 child_pid=1001");
+        assert!(matches!(action.event,
+            Events::SchedProcessFork { ref command, pid: 1000, ref child_command, child_pid: 1001 }
+                if command == "a=b" && child_command == "c child_pid=7"));
+    }
+
+    #[test]
+    fn switch_commands_with_separators() {
+        let action = action_from("kworker/0:1-events-77  [000]  100.000100: sched_switch: kworker/0:1-events:77 [120] I ==> web-2 worker:1600 [120]");
+        assert_eq!(action.process, "kworker/0:1-events");
+        assert_eq!(action.pid, 77);
+        assert!(matches!(action.event,
+            Events::SchedSwitch { ref old_command, old_pid: 77, ref new_command, new_pid: 1600, .. }
+                if old_command == "kworker/0:1-events" && new_command == "web-2 worker"));
+    }
+
+    #[test]
+    fn wakeup_new_without_fork() {
+        let reader = read_trace("tracing-tool-orphan-test.txt", "cpus=2
+            bash-1000  [000]  100.000100: sched_wakeup:         bash:1001 [120] CPU:001
+            bash-1000  [000]  100.000200: sched_wakeup_new:     bash:1001 [120] CPU:001
+            bash-1000  [000]  100.000300: sched_wakeup_new:     bash:1002 [120] CPU:001
+            bash-1000  [000]  100.000400: sched_process_fork:   comm=bash pid=1000 child_comm=bash child_pid=1003
+            bash-1000  [000]  100.000500: sched_wakeup_new:     bash:1003 [120] CPU:001
+", |_| {});
+        assert_eq!(reader.orphaned_wakeups, 2);
+        assert_eq!(reader.event_counts["sched_wakeup_new"], 3);
+
+        let action = action_from("bash-1000  [000]  100.000200: sched_wakeup_new: bash:1001 [120] CPU:001");
+        assert!(matches!(action.event, Events::SchedWakeupNew { parent_cpu: 1, cpu: 1, forked: false, .. }));
+    }
+
+    #[test]
+    fn unsupported_event_types_are_counted() {
+        let reader = read_trace("tracing-tool-unsupported-test.txt", "cpus=2
+            bash-1000  [000]  100.000100: sched_stat_sleep:     comm=bash pid=1001 delay=100 [ns]
+            bash-1000  [000]  100.000200: timer_start:          timer=00000000a1b2c3d4 function=tick_sched_timer
+            bash-1000  [000]  100.000300: sched_stat_sleep:     comm=bash pid=1002 delay=100 [ns]
+            bash-1000  [000]  100.000400: sched_wakeup:         bash:1001 [120] CPU:001
+", |_| {});
+        assert_eq!(reader.unsupported, 3);
+        assert_eq!(reader.unsupported_types["sched_stat_sleep"], 2);
+        assert_eq!(reader.unsupported_types["timer_start"], 1);
+        assert!(!reader.unsupported_types.contains_key("sched_wakeup"));
+    }
+
+    #[test]
+    fn cpu_idle_enter_and_exit() {
+        let action = action_from("<idle>-0  [002]  100.000100: cpu_idle: state=2 cpu_id=2");
+        assert_eq!(action.event, Events::CpuIdle { state: 2, cpu: 2 });
+        let action = action_from("<idle>-0  [002]  100.000200: cpu_idle: state=4294967295 cpu_id=2");
+        assert_eq!(action.event, Events::CpuIdle { state: IDLE_EXIT, cpu: 2 });
+    }
+
+    #[test]
+    fn pi_boost_and_restore() {
+        let action = action_from("irq/24-eth0-130  [001]  100.000100: sched_pi_setprio: comm=my app pid=1500 oldprio=120 newprio=49");
+        assert_eq!(action.event, Events::SchedPiSetprio { command: String::from("my app"), pid: 1500, old_prio: 120, new_prio: 49 });
+        // a deadline waiter has a negative prio
+        let action = action_from("my app-1500  [003]  100.000200: sched_pi_setprio: comm=my app pid=1500 oldprio=-1 newprio=120");
+        assert_eq!(action.event, Events::SchedPiSetprio { command: String::from("my app"), pid: 1500, old_prio: -1, new_prio: 120 });
+    }
+
+    #[test]
+    fn runqueue_of_a_cpu_past_the_header() {
+        let reader = read_trace("tracing-tool-hotplug-test.txt", "cpus=2
+            bash-1000  [000]  100.000100: sched_wakeup:         sleep:1001 [120] CPU:002
+", |_| {});
+        assert_eq!(reader.cpu_count, 2);
+        assert_eq!(reader.runqueue_depth(2), 1);
+    }
+
+    #[test]
+    fn concatenated_sessions() {
+        let reader = read_trace("tracing-tool-sessions-test.txt", "cpus=2
+            bash-1000  [000]  200.000100: sched_waking:         comm=sleep pid=1001 prio=120 target_cpu=001
+cpus=4
+            bash-1000  [003]  100.000100: sched_wakeup:         sleep:1001 [120] CPU:003
+", |_| {});
+        assert_eq!(reader.sessions, 2);
+        assert_eq!(reader.out_of_order, 0);
+        assert_eq!(reader.cpu_count, 2);
+        assert_eq!(reader.runqueue_depth(3), 1);
+        assert!(reader.unsupported_types.is_empty());
+    }
+
+    #[test]
+    fn numa_swap_sets_both_tasks() {
+        let mut process_state = HashMap::new();
+        let line = "bash-1000  [000]  100.001000: sched_swap_numa: src_pid=1000 src_tgid=1000 src_ngid=0 src_cpu=0 src_nid=0 dst_pid=1001 dst_tgid=1001 dst_ngid=0 dst_cpu=3 dst_nid=1";
+        get_action(&line.split_whitespace().collect(), &mut process_state);
+        assert_eq!(process_state[&1000], Wstate::Numa(0, 3));
+        assert_eq!(process_state[&1001], Wstate::Numa(3, 0));
+
+        let line = "bash-1000  [000]  100.001200: sched_migrate_task: comm=bash pid=1000 prio=120 orig_cpu=0 dest_cpu=3";
+        let action = get_action(&line.split_whitespace().collect(), &mut process_state).unwrap();
+        assert!(matches!(action.event, Events::SchedMigrateTask { state: Wstate::Numa(0, 3), .. }));
+    }
+
+    #[test]
+    fn irq_and_softirq_entry() {
+        let action = action_from("<idle>-0  [003]  100.000150: irq_handler_entry: irq=24 name=eth0");
+        assert!(matches!(action.event, Events::IrqHandlerEntry { irq: 24, ref name, cpu: 3 } if name == "eth0"));
+
+        let action = action_from("<idle>-0  [003]  100.000160: softirq_entry: vec=3 [action=NET_RX]");
+        assert!(matches!(action.event, Events::SoftirqEntry { vec: 3, ref action, cpu: 3 } if action == "NET_RX"));
+    }
+
+    #[test]
+    fn action_without_latency_column() {
+        let action = action_from("bash-1000  [000]  100.000300: sched_wakeup_new: bash:1001 [120] CPU:001");
+        assert_eq!(action.cpu, 0);
+        assert_eq!(action.timestamp, 100.000300);
+        assert!(matches!(action.event, Events::SchedWakeupNew { pid: 1001, cpu: 1, .. }));
+    }
+
+    // parse a whole trace written to a temporary file, calling f after every action
+    fn read_trace(name: &str, trace: &str, mut f: impl FnMut(&TraceParser)) -> TraceParser {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, trace).unwrap();
+        let mut reader = TraceParser::new(path.to_str().unwrap());
+        while reader.next_action().is_some() {
+            f(&reader);
+        }
+        std::fs::remove_file(&path).unwrap();
+        reader
+    }
+
+    #[test]
+    fn runqueue_depth_follows_the_task() {
+        let mut depths = Vec::new();
+        read_trace("tracing-tool-runqueue-test.txt", "cpus=2
+            bash-1000  [000]  100.000100: sched_wakeup:         sleep:1001 [120] CPU:000
+            bash-1000  [000]  100.000200: sched_migrate_task:   comm=sleep pid=1001 prio=120 orig_cpu=0 dest_cpu=1
+          <idle>-0     [001]  100.000300: sched_switch:         swapper/1:0 [120] R ==> sleep:1001 [120]
+           sleep-1001  [001]  100.000400: sched_switch:         sleep:1001 [120] S ==> swapper/1:0 [120]
+", |reader| depths.push((reader.runqueue_depth(0), reader.runqueue_depth(1))));
+        assert_eq!(depths, vec![(1, 0), (0, 1), (0, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn first_and_last_timestamps_are_min_and_max() {
+        let reader = read_trace("tracing-tool-order-test.txt", "cpus=2
+            bash-1000  [000]  100.000200: sched_waking: comm=bash pid=1001 prio=120 target_cpu=001
+            bash-1000  [001]  100.000100: sched_waking: comm=bash pid=1002 prio=120 target_cpu=001
+            bash-1000  [000]  100.000400: sched_waking: comm=bash pid=1003 prio=120 target_cpu=001
+            bash-1000  [001]  100.000300: sched_waking: comm=bash pid=1004 prio=120 target_cpu=001
+", |_| {});
+        assert_eq!(reader.start_timestamp, Some(100.000200));
+        assert_eq!(reader.first_timestamp, Some(100.000100));
+        assert_eq!(reader.last_timestamp, Some(100.000400));
+        assert_eq!(reader.out_of_order, 2);
+    }
+
+    #[test]
+    fn seek_stops_before_the_timestamp() {
+        // far larger than SEEK_PRECISION, one event every 10us
+        let mut trace = String::from("cpus=2\n");
+        for event in 0..20000 {
+            trace.push_str(&format!("bash-1000  [000]  {:.6}: sched_waking: comm=bash pid=1001 prio=120 target_cpu=001\n", 100.0 + event as f64 * 0.00001));
+        }
+        let path = std::env::temp_dir().join("tracing-tool-seek-test.txt");
+        std::fs::write(&path, trace).unwrap();
+        let mut reader = TraceParser::new(path.to_str().unwrap());
+
+        assert_eq!(reader.read_start_timestamp(), Some(100.0));
+        assert!(reader.seek_to_timestamp(100.15));
+        let (first, ..) = reader.next_action().unwrap();
+        assert!(first.timestamp < 100.15 && first.timestamp > 100.1);
+        let mut timestamps = vec![first.timestamp];
+        while let Some((action, ..)) = reader.next_action() {
+            timestamps.push(action.timestamp);
+        }
+        // every event from the seek point is read, so none at or after 100.15 is skipped
+        assert!(timestamps.windows(2).all(|pair| pair[1] - pair[0] < 0.000011));
+        assert_eq!(timestamps.last(), Some(&100.19999));
+        assert_eq!(reader.start_timestamp, Some(100.0));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
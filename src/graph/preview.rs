@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use crate::graph::parser::*;
+use crate::read_config::Config;
+use plotly::common::Title;
+use plotly::layout::{ Axis, Layout };
+use plotly::{ Bar, Plot };
+use super::{ check_skipped, find_sleep, time_origin, write_plot };
+
+// busiest windows printed to pick min and max from
+const BUSIEST_WINDOWS: usize = 5;
+
+// Activity histogram of a trace, the event count of every window of preview_bucket seconds
+// A single pass over the trace without drawing the timeline, to choose the custom_range of huge traces
+// Windows are in the same time space as min and max
+pub fn preview_graph(filepath: &str, config: &Config) {
+    let options = &config.graph;
+    let filename = filepath.split("/").last().unwrap();
+    let bucket = options.preview_bucket;
+    if bucket <= 0.0 {
+        panic!("preview_bucket must be positive, got {}", bucket);
+    }
+
+    let mut reader = TraceParser::new(filepath);
+    find_sleep(&mut reader, filepath, options);
+
+    let mut counts: HashMap<i64, u64> = HashMap::new();
+    while let Some((action, _, Some(start_timestamp))) = reader.next_action() {
+        let time = action.timestamp - time_origin(options, start_timestamp);
+        *counts.entry((time / bucket).floor() as i64).or_insert(0) += 1;
+    }
+    check_skipped(filename, &reader, options);
+    let (Some(first), Some(last)) = (counts.keys().min().copied(), counts.keys().max().copied()) else {
+        eprintln!("Warning: no events to preview in {}", filename);
+        return;
+    };
+
+    // empty windows are kept so the x axis is continuous
+    let windows: Vec<(f64, u64)> = (first..=last).map(|window| (window as f64 * bucket, counts.get(&window).copied().unwrap_or(0))).collect();
+    let mut busiest = windows.clone();
+    busiest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.total_cmp(&b.0)));
+    println!("{}: {} events in {} windows of {} seconds, busiest windows:", filename, counts.values().sum::<u64>(), windows.len(), bucket);
+    for (start, count) in busiest.iter().take(BUSIEST_WINDOWS).filter(|(_, count)| *count > 0) {
+        println!("  min = {:.*}, max = {:.*}: {} events", options.timestamp_precision, start, options.timestamp_precision, start + bucket, count);
+    }
+
+    let mut plot = Plot::new();
+    let (starts, events): (Vec<f64>, Vec<u64>) = windows.into_iter().map(|(start, count)| (start + bucket / 2.0, count)).unzip();
+    plot.add_trace(Bar::new(starts, events).name("events"));
+
+    let mut layout = Layout::new()
+                        .x_axis(
+                            Axis::new()
+                            .title(Title::new(&format!("Time (seconds), windows of {} seconds", bucket)))
+                            .show_grid(false))
+                        .y_axis(
+                            Axis::new()
+                            .title(Title::new("Events"))
+                            .show_grid(false))
+                        .bar_gap(0.0)
+                        .auto_size(true);
+    if options.show_title {
+        layout = layout.title(Title::new(format!("Preview: {}", filename).as_str()));
+    }
+    plot.set_layout(layout);
+    write_plot(&mut plot, options, &format!("{}-preview", filename));
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use crate::graph::parser::*;
+use crate::read_config::Config;
+use super::{ check_skipped, find_sleep, get_frequency_map, get_sched_switch_events, get_socket_order, migrate_category, task_states, time_origin };
+
+// migration categories of the report, in the order of the legend
+const MIGRATE_CATEGORIES: [&str; 5] = [
+    "on-socket<br>unblock placement",
+    "off-socket<br>unblock placement",
+    "on-socket<br>load balancing",
+    "off-socket<br>load balancing",
+    "numa balancing",
+];
+
+// Scheduling report of a trace printed to stdout, without building any plot
+// Migrations are classified as on the graph, the switches give the time spent on and off the cpus
+pub fn stats_report(filepath: &str, config: &Config) {
+    let options = &config.graph;
+    let filename = filepath.split("/").last().unwrap();
+    let mut reader = TraceParser::new(filepath);
+    reader.strict_order = options.strict_order;
+    reader.task_states = task_states(options);
+    find_sleep(&mut reader, filepath, options);
+
+    let mut actions: Vec<Action> = Vec::new();
+    let mut frequency = get_frequency_map();
+    let mut start_time = None;
+    while let Some((action, states, Some(start_timestamp))) = reader.next_action() {
+        let start = time_origin(options, start_timestamp);
+        start_time = Some(start);
+        // the rest of the file is past max, stop reading there as draw_traces does
+        if options.custom_range && action.timestamp - start > options.max {
+            break;
+        }
+        if options.custom_range && action.timestamp - start < options.min {
+            continue;
+        }
+        if let Events::SchedMigrateTask { pid, orig_cpu, dest_cpu, .. } = &action.event {
+            if let Some(state) = states.get(pid) {
+                let (src, _) = get_socket_order(*orig_cpu, &config.machine);
+                let (dest, _) = get_socket_order(*dest_cpu, &config.machine);
+                *frequency.get_mut(migrate_category(*state, src, dest)).unwrap() += 1;
+            }
+        }
+        if let Events::SchedSwitch { .. } = action.event {
+            actions.push(action);
+        }
+    }
+    check_skipped(filename, &reader, options);
+    let Some(start_time) = start_time else {
+        eprintln!("Warning: no events to report in {}", filename);
+        return;
+    };
+    let precision = options.timestamp_precision;
+    let (switches, duplicates) = get_sched_switch_events(&actions);
+
+    println!("{}", filename);
+    println!("  migrations: {}", MIGRATE_CATEGORIES.iter().map(|category| frequency[*category]).sum::<u32>());
+    for category in MIGRATE_CATEGORIES {
+        println!("    {:<32} {}", category.replace("<br>", " "), frequency[category]);
+    }
+
+    println!("  switches: {} ({} duplicates dropped)", switches.values().map(Vec::len).sum::<usize>(), duplicates);
+    let mut cpus: Vec<&u32> = switches.keys().collect();
+    cpus.sort();
+    for cpu in cpus {
+        println!("    cpu {:<28} {}", cpu, switches[cpu].len());
+    }
+
+    // a task runs from its switch in to the next switch on the same cpu
+    let mut on_cpu: HashMap<u32, (String, f64)> = HashMap::new();
+    for events in switches.values() {
+        for pair in events.windows(2) {
+            if let Events::SchedSwitch { new_command, new_pid, .. } = &pair[0].event {
+                if *new_pid != 0 {
+                    on_cpu.entry(*new_pid).or_insert((new_command.clone(), 0.0)).1 += pair[1].timestamp - pair[0].timestamp;
+                }
+            }
+        }
+    }
+    match on_cpu.iter().max_by(|a, b| a.1.1.total_cmp(&b.1.1).then(b.0.cmp(a.0))) {
+        Some((pid, (command, time))) => println!("  busiest task: {}:{}, {:.*} seconds on cpu", command, pid, precision, time),
+        None => println!("  busiest task: none"),
+    }
+
+    // a task is in D state from its switch out with that state to its next switch in, on any cpu
+    let mut ordered: Vec<&Action> = switches.values().flatten().copied().collect();
+    ordered.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    let mut blocked: HashMap<u32, f64> = HashMap::new();
+    let mut longest: Option<(String, u32, f64, f64)> = None;
+    for action in ordered {
+        if let Events::SchedSwitch { old_pid, state, new_command, new_pid, .. } = &action.event {
+            if let Some(since) = blocked.remove(new_pid) {
+                let duration = action.timestamp - since;
+                if longest.as_ref().is_none_or(|(.., longest_duration)| duration > *longest_duration) {
+                    longest = Some((new_command.clone(), *new_pid, since, duration));
+                }
+            }
+            if classify_state(state, &reader.task_states) == TaskState::Uninterruptible && *old_pid != 0 {
+                blocked.insert(*old_pid, action.timestamp);
+            }
+        }
+    }
+    match longest {
+        Some((command, pid, since, duration)) => println!("  longest D state: {}:{}, {:.*} seconds from {:.*}",
+                                                        command, pid, precision, duration, precision, since - start_time),
+        None => println!("  longest D state: none"),
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+// State of a task switched out, from the state field of sched_switch
+// The kernel writes one or more letters, like R+ for a preempted task or D|K for a killable uninterruptible sleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    // preempted, the task stays runnable
+    Running,
+    // interruptible sleep, waiting for an event
+    Sleeping,
+    // uninterruptible sleep, usually waiting on I/O or a lock
+    Uninterruptible,
+    // stopped by a signal or by a tracer
+    Stopped,
+    // exiting, as a zombie or dead task
+    Dead,
+    // idle kernel thread, waiting for work
+    Idle,
+    // a state this classification does not know
+    Unknown,
+}
+
+// names of the states in the [graph.task_states] table of the config
+pub const TASK_STATES: [(&str, TaskState); 7] = [
+    ("running", TaskState::Running),
+    ("sleeping", TaskState::Sleeping),
+    ("uninterruptible", TaskState::Uninterruptible),
+    ("stopped", TaskState::Stopped),
+    ("dead", TaskState::Dead),
+    ("idle", TaskState::Idle),
+    ("unknown", TaskState::Unknown),
+];
+
+pub fn parse_task_state(name: &str) -> Option<TaskState> {
+    TASK_STATES.iter().find(|(state_name, _)| *state_name == name).map(|(_, state)| *state)
+}
+
+// class of a state string, overrides maps whole state strings of unusual kernels to their class
+// Otherwise the first letter decides, the flags after it (+, |K, |W...) do not change the class
+pub fn classify_state(state: &str, overrides: &HashMap<String, TaskState>) -> TaskState {
+    if let Some(state) = overrides.get(state) {
+        return *state;
+    }
+    match state.chars().next() {
+        Some('R') => TaskState::Running,
+        // parked kernel threads sleep until they are unparked
+        Some('S') | Some('P') => TaskState::Sleeping,
+        Some('D') => TaskState::Uninterruptible,
+        Some('T') | Some('t') => TaskState::Stopped,
+        Some('X') | Some('Z') => TaskState::Dead,
+        Some('I') => TaskState::Idle,
+        _ => TaskState::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compound_states_are_classified_by_their_first_letter() {
+        let classify = |state| classify_state(state, &HashMap::new());
+        assert_eq!(classify("R"), TaskState::Running);
+        assert_eq!(classify("R+"), TaskState::Running);
+        assert_eq!(classify("S"), TaskState::Sleeping);
+        assert_eq!(classify("D"), TaskState::Uninterruptible);
+        assert_eq!(classify("D|K"), TaskState::Uninterruptible);
+        assert_eq!(classify("t"), TaskState::Stopped);
+        assert_eq!(classify("Z"), TaskState::Dead);
+        assert_eq!(classify("I"), TaskState::Idle);
+        assert_eq!(classify(""), TaskState::Unknown);
+        assert_eq!(classify("0x402"), TaskState::Unknown);
+    }
+
+    #[test]
+    fn overrides_come_first() {
+        let overrides = HashMap::from([(String::from("K"), TaskState::Uninterruptible), (String::from("R+"), TaskState::Sleeping)]);
+        assert_eq!(classify_state("K", &overrides), TaskState::Uninterruptible);
+        assert_eq!(classify_state("R+", &overrides), TaskState::Sleeping);
+        assert_eq!(classify_state("R", &overrides), TaskState::Running);
+    }
+}
@@ -0,0 +1,100 @@
+// Waking state of a task, moved along by the scheduler events of the trace
+// It tells a migration apart as unblock placement, load balancing or numa balancing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wstate {
+    // being woken up or forked, from the cpu of the waker to the target cpu, at the timestamp of the waking or fork
+    Waking(u32, u32, f64),
+    // running or runnable, any migration is load balancing
+    Woken,
+    // picked by numa balancing to go from the first cpu to the second
+    Numa(i32, i32)
+}
+
+// The part of an event that moves the waking state of a single task
+#[derive(Debug)]
+pub enum RawEvent {
+    // sched_waking, seen on the cpu of the waker
+    Waking { waker_cpu: u32, target_cpu: u32, timestamp: f64 },
+    // sched_wakeup and sched_wakeup_new
+    Wakeup,
+    // sched_process_fork, for the child
+    Fork { parent_cpu: u32, timestamp: f64 },
+    // sched_swap_numa for both tasks and sched_move_numa, from the task's cpu to the other one
+    Numa { src_cpu: i32, dst_cpu: i32 },
+    // sched_migrate_task
+    Migrate { orig_cpu: u32, dest_cpu: u32 },
+}
+
+// State of the task after the event, current is None for a task not seen before
+// A migration keeps the state it finds, except a numa decision for another move, which is dropped
+pub fn transition(current: Option<Wstate>, event: &RawEvent) -> Wstate {
+    match event {
+        RawEvent::Waking { waker_cpu, target_cpu, timestamp } => Wstate::Waking(*waker_cpu, *target_cpu, *timestamp),
+        RawEvent::Wakeup => Wstate::Woken,
+        RawEvent::Fork { parent_cpu, timestamp } => Wstate::Waking(*parent_cpu, *parent_cpu, *timestamp),
+        RawEvent::Numa { src_cpu, dst_cpu } => Wstate::Numa(*src_cpu, *dst_cpu),
+        RawEvent::Migrate { orig_cpu, dest_cpu } => match current {
+            Some(Wstate::Numa(src, dst)) if src != *orig_cpu as i32 || dst != *dest_cpu as i32 => Wstate::Woken,
+            Some(state) => state,
+            None => Wstate::Woken,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the states of a task going through the events in order
+    fn states_after(events: &[RawEvent]) -> Vec<Wstate> {
+        let mut current = None;
+        events.iter().map(|event| {
+            let state = transition(current, event);
+            current = Some(state);
+            state
+        }).collect()
+    }
+
+    #[test]
+    fn waking_to_woken() {
+        let states = states_after(&[
+            RawEvent::Waking { waker_cpu: 0, target_cpu: 2, timestamp: 1.0 },
+            RawEvent::Migrate { orig_cpu: 1, dest_cpu: 2 },
+            RawEvent::Wakeup,
+            RawEvent::Migrate { orig_cpu: 2, dest_cpu: 3 },
+        ]);
+        assert_eq!(states, vec![Wstate::Waking(0, 2, 1.0), Wstate::Waking(0, 2, 1.0), Wstate::Woken, Wstate::Woken]);
+    }
+
+    #[test]
+    fn fork_to_wakeup_new() {
+        let states = states_after(&[
+            RawEvent::Fork { parent_cpu: 1, timestamp: 1.0 },
+            RawEvent::Migrate { orig_cpu: 1, dest_cpu: 3 },
+            RawEvent::Wakeup,
+        ]);
+        assert_eq!(states, vec![Wstate::Waking(1, 1, 1.0), Wstate::Waking(1, 1, 1.0), Wstate::Woken]);
+    }
+
+    #[test]
+    fn numa_swap_is_kept_by_its_migration_only() {
+        let states = states_after(&[
+            RawEvent::Numa { src_cpu: 0, dst_cpu: 4 },
+            RawEvent::Migrate { orig_cpu: 0, dest_cpu: 4 },
+        ]);
+        assert_eq!(states, vec![Wstate::Numa(0, 4), Wstate::Numa(0, 4)]);
+
+        let states = states_after(&[
+            RawEvent::Numa { src_cpu: 0, dst_cpu: 4 },
+            RawEvent::Migrate { orig_cpu: 0, dest_cpu: 1 },
+            RawEvent::Migrate { orig_cpu: 0, dest_cpu: 4 },
+        ]);
+        assert_eq!(states, vec![Wstate::Numa(0, 4), Wstate::Woken, Wstate::Woken]);
+    }
+
+    #[test]
+    fn unknown_task_is_woken() {
+        assert_eq!(transition(None, &RawEvent::Migrate { orig_cpu: 0, dest_cpu: 1 }), Wstate::Woken);
+        assert_eq!(transition(None, &RawEvent::Wakeup), Wstate::Woken);
+    }
+}
@@ -0,0 +1,4 @@
+#![allow(clippy::too_many_arguments)]
+
+pub mod graph;
+pub mod read_config;
@@ -1,26 +1,118 @@
-pub mod graph;
-pub mod read_config;
-
-use std::process::Command;
-use std::fs::File;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+use std::fs::{canonicalize, File};
 use std::fs::remove_file;
 use std::io::Write;
-use graph::*;
-use read_config::{config, Config};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde_json::json;
+use tracing_tool::graph::*;
+use tracing_tool::graph::parser::TraceParser;
+use tracing_tool::read_config::{config, Config};
+
+// quiet time after the last write to a watched file before it is plotted again
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
 
 fn main() {
     let config = config();
-    for arg in &config.graph.files {
-        make_graph(&arg, &config);
+    // ctrl-c while parsing a trace still writes the plot of the events read so far
+    ctrlc::set_handler(interrupt).expect("Failed to set the ctrl-c handler");
+    if config.graph.dry_run {
+        if !dry_run(&config) {
+            exit(1);
+        }
+        return;
+    }
+    if let Some(filepath) = &config.graph.info {
+        make_graph(filepath, &config, print_info);
+        return;
+    }
+    if config.graph.combine {
+        combine_graphs(&config);
+    } else if config.graph.merge_files {
+        merge_graphs(&config);
+    } else if !config.graph.diff.is_empty() {
+        diff_graphs(&config);
+    } else if config.graph.benchmark {
+        benchmark_graphs(config);
+    } else {
+        let draw = if config.graph.stats_only {
+            stats::stats_report
+        } else if config.graph.preview {
+            preview::preview_graph
+        } else if config.graph.split_sessions {
+            combine::sessions_graph
+        } else {
+            data_graph
+        };
+        for arg in &config.graph.files {
+            make_graph(arg, &config, draw);
+            if interrupted() {
+                exit(130);
+            }
+        }
+        if config.graph.watch {
+            watch(config, draw);
+        }
+    }
+    // every file is still plotted, so the report of each one is there to look at
+    if too_many_skipped() {
+        exit(2);
     }
 }
 
-// if file_extension = .dat, get the trace-cmd report and plot
-fn make_graph(filepath: &String, config:&Config) {
-    let filepath = filepath;
+// Plot a file again whenever it is written to, until the process is interrupted
+// A growing trace is written in bursts, so the files are plotted once the writes pause for WATCH_DEBOUNCE
+fn watch(mut config: Config, draw: fn(&str, &Config)) {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).unwrap_or_else(|err| {
+        eprintln!("Error: cannot watch files: {}", err);
+        exit(1);
+    });
+    let mut watched: Vec<(String, PathBuf)> = Vec::new();
+    for filepath in &config.graph.files {
+        if let Err(err) = watcher.watch(Path::new(filepath), RecursiveMode::NonRecursive) {
+            eprintln!("Error: cannot watch {}: {}", filepath, err);
+            exit(1);
+        }
+        watched.push((filepath.clone(), canonicalize(filepath).unwrap_or_else(|_| PathBuf::from(filepath))));
+    }
+
+    // the browser is opened once, the next plots only overwrite the html
+    if config.graph.show_html {
+        config.graph.show_html = false;
+        config.graph.create_html = true;
+    }
+    eprintln!("Watching {} files, every change parses the whole trace again, reload the page to see it", watched.len());
+
+    let modified = |event: notify::Result<Event>, changed: &mut HashSet<PathBuf>| {
+        if let Ok(event) = event {
+            if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_)) {
+                changed.extend(event.paths.iter().map(|path| canonicalize(path).unwrap_or(path.clone())));
+            }
+        }
+    };
+    while let Ok(event) = receiver.recv() {
+        let mut changed = HashSet::new();
+        modified(event, &mut changed);
+        while let Ok(event) = receiver.recv_timeout(WATCH_DEBOUNCE) {
+            modified(event, &mut changed);
+        }
+        for (filepath, path) in &watched {
+            if changed.contains(path) {
+                make_graph(filepath, &config, draw);
+            }
+        }
+    }
+}
+
+// if file_extension = .dat, write the trace-cmd report to a text file
+// returns the name of the generated file
+fn report_trace(filepath: &String) -> Option<String> {
     let filename = filepath.split("/").last().unwrap();
 
-    let trace_name: String;
     if let Some((name, "dat")) = filename.rsplit_once(".") {
         let output = Command::new("trace-cmd")
                 .arg("report")
@@ -28,15 +120,147 @@ fn make_graph(filepath: &String, config:&Config) {
                 .output()
                 .expect("Trace-cmd failed on dat file");
         
-        trace_name = format!("{}.txt", name);
+        let trace_name = format!("{}.txt", name);
         let mut writer = File::create(trace_name.clone()).expect("Failed to create trace");
         writer.write_all(&output.stdout).expect("Error while writing trace");
+        return Some(trace_name);
+    }
+    None
+}
+
+// if file_extension = .dat, get the trace-cmd report and plot
+fn make_graph(filepath: &String, config:&Config, draw: fn(&str, &Config)) {
+    match report_trace(filepath) {
+        Some(trace_name) => {
+            draw(&trace_name, config);
+            remove_file(&trace_name).expect("couldn't remove generated trace file");
+        }
+        None => draw(filepath, config),
+    }
+}
+
+// plot every file and print the time of its stages
+// the plot is only written, as opening it in the browser would be timed as well
+fn benchmark_graphs(mut config: Config) {
+    if config.graph.show_html {
+        config.graph.show_html = false;
+        config.graph.create_html = true;
+    }
+    benchmark::start();
+    for filepath in &config.graph.files {
+        let start = Instant::now();
+        let report = report_trace(filepath);
+        if report.is_some() {
+            benchmark::record("trace-cmd report", start.elapsed());
+        }
+        let trace_name = report.clone().unwrap_or(filepath.clone());
+        data_graph(&trace_name, &config);
+        let total = start.elapsed();
+        if let Some(trace_name) = report {
+            remove_file(trace_name).expect("couldn't remove generated trace file");
+        }
+        benchmark::print_table(filepath, benchmark::take(), total);
+        if interrupted() {
+            exit(130);
+        }
+    }
+}
+
+// plot all the files together, one subplot per file
+fn combine_graphs(config: &Config) {
+    let reports: Vec<Option<String>> = config.graph.files.iter().map(report_trace).collect();
+    let traces: Vec<String> = config.graph.files.iter().zip(&reports)
+                                .map(|(filepath, report)| report.clone().unwrap_or(filepath.clone()))
+                                .collect();
+
+    combine::combined_graph(&traces, config, "combined", "Comparison");
+
+    for trace_name in reports.iter().flatten() {
+        remove_file(trace_name).expect("couldn't remove generated trace file");
+    }
+}
+
+// plot all the files as a single trace, each file bringing its own cpus
+fn merge_graphs(config: &Config) {
+    let reports: Vec<Option<String>> = config.graph.files.iter().map(report_trace).collect();
+    let traces: Vec<String> = config.graph.files.iter().zip(&reports)
+                                .map(|(filepath, report)| report.clone().unwrap_or(filepath.clone()))
+                                .collect();
+
+    combine::merged_graph(&traces, config);
+
+    for trace_name in reports.iter().flatten() {
+        remove_file(trace_name).expect("couldn't remove generated trace file");
+    }
+}
 
-        data_graph(&trace_name, config);
+// plot the candidate of --diff against its baseline
+fn diff_graphs(config: &Config) {
+    let reports: Vec<Option<String>> = config.graph.diff.iter().map(report_trace).collect();
+    let traces: Vec<String> = config.graph.diff.iter().zip(&reports)
+                                .map(|(filepath, report)| report.clone().unwrap_or(filepath.clone()))
+                                .collect();
 
-        remove_file(&trace_name).expect("couldn't remove generated trace file");
+    diff::diff_graph(&traces[0], &traces[1], config);
+
+    for trace_name in reports.iter().flatten() {
+        remove_file(trace_name).expect("couldn't remove generated trace file");
     }
-    else {
-        data_graph(filepath, config);
+}
+
+// time span of a trace as json, for scripts going over many traces
+fn print_info(filepath: &str, _config: &Config) {
+    let mut reader = TraceParser::try_new(filepath).unwrap_or_else(|err| {
+        eprintln!("Error: {}: {}", filepath, err);
+        exit(1);
+    });
+    reader.scan_timestamps();
+    let duration = reader.last_timestamp.zip(reader.first_timestamp).map(|(last, first)| last - first);
+    println!("{}", json!({
+        "cpus": reader.cpu_count,
+        "first_ts": reader.first_timestamp,
+        "last_ts": reader.last_timestamp,
+        "duration": duration,
+    }));
+}
+
+// check the machine config and the header of every file, without plotting anything
+// returns false if any problem was found
+fn dry_run(config: &Config) -> bool {
+    let mut ok = true;
+
+    let problems = config.machine.problems();
+    if problems.is_empty() {
+        println!("machine: OK");
     }
-}
\ No newline at end of file
+    for problem in problems {
+        println!("machine: error: {}", problem);
+        ok = false;
+    }
+
+    for filepath in &config.graph.files {
+        if filepath.ends_with(".dat") {
+            // the header is only known after trace-cmd report
+            if Path::new(filepath).is_file() {
+                println!("{}: OK (dat file, header not checked)", filepath);
+            } else {
+                println!("{}: error: file not found", filepath);
+                ok = false;
+            }
+            continue;
+        }
+
+        match TraceParser::try_new(filepath) {
+            Ok(reader) if reader.cpu_count != config.machine.cpus => {
+                println!("{}: OK, but the trace has {} cpus while the machine config has {}",
+                            filepath, reader.cpu_count, config.machine.cpus);
+            }
+            Ok(reader) => println!("{}: OK ({} cpus)", filepath, reader.cpu_count),
+            Err(err) => {
+                println!("{}: error: {}", filepath, err);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
@@ -1,7 +1,11 @@
-use std::fs::read_to_string;
+use std::collections::HashMap;
+use std::fs::{ create_dir_all, read_to_string };
 use serde::Deserialize;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
+use std::process::exit;
+use toml::{ Table, Value };
 use clap_serde_derive::{
     clap::{self},
     serde::Serialize,
@@ -17,28 +21,300 @@ pub struct Machine {
     pub threads_per_core: u32,
     pub numa_nodes: u32,
     pub numa_node_ranges: Vec<Vec<Vec<u32>>>,
+    // hops between every pair of numa nodes, nodes are assumed to be connected in a line if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_hops: Option<Vec<Vec<u32>>>,
+}
+
+// cpu numbers of a sysfs cpu list, e.g. 0-15,32-47
+fn parse_cpu_list(list: &str) -> Result<Vec<u32>, String> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let start: u32 = start.parse().map_err(|_| format!("invalid cpu list {}", list.trim()))?;
+        let end: u32 = end.parse().map_err(|_| format!("invalid cpu list {}", list.trim()))?;
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+// contiguous ranges of sorted cpu numbers, as [start, end] pairs
+fn cpu_ranges(cpus: &[u32]) -> Vec<Vec<u32>> {
+    let mut ranges: Vec<Vec<u32>> = Vec::new();
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some(range) if range[1] + 1 == *cpu => range[1] = *cpu,
+            _ => ranges.push(vec![*cpu, *cpu]),
+        }
+    }
+    ranges
+}
+
+fn read_sys(path: &str) -> Result<String, String> {
+    read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))
+}
+
+impl Machine {
+    // topology of the current host from /sys/devices/system, numa nodes are assumed to be the sockets
+    pub fn detect() -> Result<Machine, String> {
+        let online = parse_cpu_list(&read_sys("/sys/devices/system/cpu/online")?)?;
+        let mut packages = Vec::new();
+        let mut cores = Vec::new();
+        for cpu in &online {
+            let topology = format!("/sys/devices/system/cpu/cpu{}/topology", cpu);
+            let package: u32 = read_sys(&format!("{}/physical_package_id", topology))?.trim().parse().unwrap_or(0);
+            let core: u32 = read_sys(&format!("{}/core_id", topology))?.trim().parse().unwrap_or(0);
+            if !packages.contains(&package) {
+                packages.push(package);
+            }
+            if !cores.contains(&(package, core)) {
+                cores.push((package, core));
+            }
+        }
+
+        // without numa, all cpus are on a single node
+        let mut numa_node_ranges = Vec::new();
+        for node in 0.. {
+            let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+            if !Path::new(&path).exists() {
+                break;
+            }
+            let mut cpus = parse_cpu_list(&read_sys(&path)?)?;
+            cpus.sort();
+            // memory only nodes have no cpus
+            if !cpus.is_empty() {
+                numa_node_ranges.push(cpu_ranges(&cpus));
+            }
+        }
+        if numa_node_ranges.is_empty() {
+            numa_node_ranges.push(cpu_ranges(&online));
+        }
+
+        let cpus = online.len() as u32;
+        let sockets = packages.len() as u32;
+        let cores_per_socket = cores.len() as u32 / sockets.max(1);
+        Ok(Machine {
+            cpus,
+            sockets,
+            cores_per_socket,
+            threads_per_core: cpus / (cores.len() as u32).max(1),
+            numa_nodes: numa_node_ranges.len() as u32,
+            numa_node_ranges,
+            node_hops: None,
+        })
+    }
+
+    // inconsistencies in the machine description, empty if there are none
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.sockets * self.cores_per_socket * self.threads_per_core != self.cpus {
+            problems.push(format!("sockets * cores_per_socket * threads_per_core = {}, but cpus = {}",
+                                    self.sockets * self.cores_per_socket * self.threads_per_core, self.cpus));
+        }
+        if self.numa_node_ranges.len() != self.numa_nodes as usize {
+            problems.push(format!("numa_nodes = {}, but numa_node_ranges has {} nodes",
+                                    self.numa_nodes, self.numa_node_ranges.len()));
+        }
+        // the socket of a cpu is the index of the node containing it
+        if self.numa_node_ranges.len() != self.sockets as usize {
+            problems.push(format!("sockets = {}, but numa_node_ranges has {} nodes",
+                                    self.sockets, self.numa_node_ranges.len()));
+        }
+
+        if let Some(node_hops) = &self.node_hops {
+            if node_hops.len() != self.numa_node_ranges.len() || node_hops.iter().any(|row| row.len() != node_hops.len()) {
+                problems.push(format!("node_hops is not a {} x {} matrix, one row and column per numa node",
+                                        self.numa_node_ranges.len(), self.numa_node_ranges.len()));
+            }
+        }
+
+        // socket order expects every socket to have a range of cores_per_socket cpus per thread,
+        // each starting at a multiple of cores_per_socket, otherwise the rows overlap
+        for (socket, ranges) in self.numa_node_ranges.iter().enumerate() {
+            if ranges.len() != self.threads_per_core as usize {
+                problems.push(format!("threads_per_core = {}, but node {} has {} ranges",
+                                        self.threads_per_core, socket, ranges.len()));
+            }
+            for range in ranges.iter().filter(|range| range.len() == 2 && range[0] <= range[1]) {
+                if range[1] - range[0] + 1 != self.cores_per_socket || range[0] % self.cores_per_socket.max(1) != 0 {
+                    problems.push(format!("numa node range {:?} of node {} is not cores_per_socket = {} cpus starting at a multiple of it",
+                                            range, socket, self.cores_per_socket));
+                }
+            }
+        }
+
+        // every cpu should be in exactly one range
+        let mut covered = vec![0; self.cpus as usize];
+        for range in self.numa_node_ranges.iter().flatten() {
+            if range.len() != 2 || range[0] > range[1] {
+                problems.push(format!("invalid numa node range {:?}", range));
+                continue;
+            }
+            for cpu in range[0]..=range[1] {
+                match covered.get_mut(cpu as usize) {
+                    Some(count) => *count += 1,
+                    None => problems.push(format!("cpu {} in numa_node_ranges is not below cpus = {}", cpu, self.cpus)),
+                }
+            }
+        }
+        for (cpu, count) in covered.iter().enumerate() {
+            if *count != 1 {
+                problems.push(format!("cpu {} is in {} numa node ranges", cpu, count));
+            }
+        }
+        problems
+    }
 }
 
 #[derive(ClapSerde, Serialize, Deserialize)]
 #[derive(Debug, Clone)]
 #[command(about = "Visualize trace-cmd report")]
 pub struct Graph {
-    /// Available color options: pid, command, parent
-    #[arg(long, default_value = "pid", required = false)]
+    /// Available color options: pid, command, parent, prio, cgroup
+    #[default(String::from("pid"))]
+    #[arg(long, required = false)]
     pub color_by: String,
 
+    /// Regex extracting the group of a task from its command for color_by cgroup, its first capture group or whole match
+    #[arg(long, required = false)]
+    pub cgroup_regex: Option<String>,
+
+    /// Most distinct task colors with color_by pid, command or cgroup, the tasks in the fewest switches past it are gray
+    #[arg(long, required = false)]
+    pub max_colors: Option<usize>,
+
+    /// Available view options: cpu (one row per cpu), socket (one row per numa node)
+    #[default(String::from("cpu"))]
+    #[arg(long, required = false)]
+    pub view: String,
+
+    /// Available migrate color options: category (placement or balancing, on or off socket), distance (numa hops)
+    #[default(String::from("category"))]
+    #[arg(long, required = false)]
+    pub migrate_color: String,
+
+    /// Order of the event legends, available options: fixed, frequency_desc, name
+    #[default(String::from("fixed"))]
+    #[arg(long, required = false)]
+    pub legend_sort: String,
+
+    /// Only draw the migrations between numa nodes at least this many hops apart, 0 draws them all
+    #[arg(long, required = false)]
+    pub min_migration_distance: u32,
+
     /// Whether cpus in the same socket should be grouped together
     #[arg(long, required = false)]
     pub socket_order: bool,
+
+    /// Draw grid lines on the time and cpu axes
+    #[arg(long, required = false)]
+    pub show_grid: bool,
+
+    /// Label and grid line every cpu_tick_step rows, restarting at each socket with socket_order, 0 for automatic ticks
+    #[arg(long, required = false)]
+    pub cpu_tick_step: u32,
     
-    /// Start plot after first sleep command
+    /// Only plot these cpus, all cpus are plotted if empty
+    #[arg(long, required = false)]
+    pub cpu_filter: Vec<u32>,
+
+    /// Rows of the cpus from the top of the list, overriding socket_order, the cpus not listed follow in natural order
+    #[arg(long, required = false)]
+    pub cpu_order: Vec<u32>,
+
+    /// Faint lines between the rows of the numa nodes, with the node name at its first row
+    #[arg(long, required = false)]
+    pub node_separators: bool,
+
+    /// Only give a row to the cpus running something else than idle
+    #[arg(long, required = false)]
+    pub hide_idle_cpus: bool,
+
+    /// Empty rows added below the first and above the last row of the cpu axis
+    #[arg(long, required = false)]
+    pub pad_y: u32,
+
+    /// Only plot this cpu, as a tall lane with the commands written on the wide segments
+    #[arg(long, required = false)]
+    pub single_cpu: Option<u32>,
+
+    /// Start plot after the first start marker event, by default the exit of a sleep command
     #[arg(long, required = false)]
     pub sleep: bool,
 
+    /// Command whose event marks the start of the plot
+    #[default(String::from("sleep"))]
+    #[arg(long, required = false)]
+    pub start_marker_command: String,
+
+    /// Event of the start marker command to start at, available options: exit, fork, exec
+    #[default(String::from("exit"))]
+    #[arg(long, required = false)]
+    pub start_marker_event: String,
+
     /// Show trace file name as title on top of graph
     #[arg(long, required = false)]
     pub show_title: bool,
 
+    /// Stop with an error on out of order timestamps instead of warning
+    #[arg(long, required = false)]
+    pub strict_order: bool,
+
+    /// Companion plot with a bar per task from its fork to its exit
+    #[arg(long, required = false)]
+    pub lifetime_view: bool,
+
+    /// Companion bar chart of the on-cpu time of every command
+    #[arg(long, required = false)]
+    pub occupancy_chart: bool,
+
+    /// Footer with the path, size and sha-256 of the trace, and the graph config as its hover text
+    #[arg(long, required = false)]
+    pub embed_provenance: bool,
+
+    /// End the plot at the exit of this command
+    #[arg(long, required = false)]
+    pub end_marker_command: Option<String>,
+
+    /// Stack all files as subplots of a single plot sharing the duration axis
+    #[arg(long, required = false)]
+    pub combine: bool,
+
+    /// Plot all files as a single trace, the files having events on separate cpus of the same machine
+    #[arg(long, required = false)]
+    pub merge_files: bool,
+
+    /// One subplot per report of a file made of concatenated reports, each starting with its cpus= line
+    #[arg(long, required = false)]
+    pub split_sessions: bool,
+
+    /// Overlay the number of runnable tasks over time on a secondary y axis
+    #[arg(long, required = false)]
+    pub runqueue_depth: bool,
+
+    /// Overlay the context switches per second of every cpu on another secondary y axis
+    #[arg(long, required = false)]
+    pub show_switch_rate: bool,
+
+    /// Length in seconds of the windows the switch rate is counted over
+    #[default(0.01)]
+    #[arg(long, required = false)]
+    pub switch_rate_window: f64,
+
+    /// Heat strip above the cpu rows with the number of events of every activity_bucket window
+    #[arg(long, required = false)]
+    pub activity_strip: bool,
+
+    /// Length in seconds of the windows of the activity strip
+    #[default(0.01)]
+    #[arg(long, required = false)]
+    pub activity_bucket: f64,
+
+    /// Busy percentage of every cpu over the plotted range, written at the right of its row
+    #[arg(long, required = false)]
+    pub show_utilization: bool,
+
     /// Whether to create a html plot
     #[arg(long, required = false)]
     pub create_html: bool,
@@ -47,22 +323,88 @@ pub struct Graph {
     #[arg(long, required = false)]
     pub interactive: bool,
 
+    /// Write plotly.js into the html instead of loading it from the plotly cdn
+    #[default(true)]
+    #[arg(long, required = false)]
+    pub embed_plotlyjs: bool,
+
+    /// Decimal places of the timestamps in the hover text and the duration label
+    #[default(6)]
+    #[arg(long, required = false)]
+    pub timestamp_precision: usize,
+
+    /// Opacity of the switch lines, below 1.0 overlapping lines reveal how dense they are
+    #[default(1.0)]
+    #[arg(long, required = false)]
+    pub line_opacity: f64,
+
+    /// Width of the switch lines
+    #[default(1.0)]
+    #[arg(long, required = false)]
+    pub line_width: f64,
+
+    /// Width of the migration lines, above line_width to keep them visible among the switch lines
+    #[default(1.0)]
+    #[arg(long, required = false)]
+    pub migrate_line_width: f64,
+
+    /// Draw an arrow from the cpu of the waker to the cpu of the wakee for every waking and wakeup
+    #[arg(long, required = false)]
+    pub wakeup_arrows: bool,
+
     /// Transparent markers to display when hovering on a line
     #[arg(long, required = false)]
     pub line_marker_count: u32,
 
+    /// Group all segments of a pid across cpus so the task highlights as one
+    #[arg(long, required = false)]
+    pub highlight_pid_on_hover: bool,
+
+    /// Color and highlight the threads of a process as their group leader, approximated from the forks and execs of the trace
+    #[arg(long, required = false)]
+    pub group_by_tgid: bool,
+
     /// Ignore switch events smaller than limit when not interative
     #[arg(long, required = false)]
     pub limit: f64,
 
+    /// Most switch segments to draw, the shortest ones of each cpu are dropped above it
+    #[arg(long, required = false)]
+    pub max_segments: Option<usize>,
+
+    /// Draw a switch segment in the same trace as the previous one of its task on the cpu when the gap between them is shorter, in seconds
+    #[arg(long, required = false)]
+    pub merge_adjacent_below: f64,
+
     /// Webgl improves performance but may cause pixelation
     #[arg(long, required = false)]
     pub webgl: bool,
 
+    /// Plot the trace timestamps instead of the time since the start, min and max are then absolute too
+    #[arg(long, required = false)]
+    pub absolute_time: bool,
+
+    /// Available hover time options: absolute (trace timestamps), relative (seconds since the start of the plot)
+    #[default(String::from("absolute"))]
+    #[arg(long, required = false)]
+    pub hover_time: String,
+
+    /// Labeled vertical lines at these times, given as time=label on the command line
+    #[arg(long, required = false, value_parser = parse_annotation)]
+    pub annotations: Vec<(f64, String)>,
+
     /// To select a portion of the trace to plot
     #[arg(long, required = false)]
     pub custom_range: bool,
 
+    /// Wall-clock time of the first event of the trace in RFC3339, to show dates on the x axis
+    #[arg(long, required = false)]
+    pub trace_start_wallclock: Option<String>,
+
+    /// Open the plot zoomed on the first time this command runs
+    #[arg(long, required = false)]
+    pub focus_command: Option<String>,
+
     /// the lower limit of the displayed range
     #[arg(long, required = false)]
     pub min: f64,
@@ -71,18 +413,120 @@ pub struct Graph {
     #[arg(long, required = false)]
     pub max: f64,
 
+    /// Seek close to min in the trace file instead of reading every line before it
+    #[default(true)]
+    #[arg(long, required = false)]
+    pub seek_to_min: bool,
+
     /// Whether to show the generated plot
     #[arg(long, required = false)]
     pub show_html: bool,
 
+    /// Never open a browser, whatever show_html and browser are set to
+    #[arg(long, required = false)]
+    pub headless: bool,
+
+    /// Json object merged into the plotly layout of the html plots, for the styling that has no option
+    #[arg(long, required = false)]
+    pub plotly_layout_overrides: Option<String>,
+
     /// Browser program name, will use default if empty
     #[arg(long, required = false)]
     pub browser: String,
 
-    /// Output location for the plots, default is current directory
-    #[arg(long, default_value = "", required = false)]
+    /// Output location for the plots, default is current directory, created if missing
+    #[arg(long, required = false)]
     pub output_path: String,
 
+    /// Do not print the parse statistics after each file
+    #[arg(long, required = false)]
+    pub quiet: bool,
+
+    /// Config file to use instead of ./tracing-tool-config.toml, it is not generated if missing
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub config: Option<String>,
+
+    /// Name of a [profiles.<name>] section of the config applied over [graph]
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub profile: Option<String>,
+
+    /// Print the default config in this format and exit, available options: toml, json
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub print_default_config: Option<String>,
+
+    /// Print the config used for this run, after the file, profile and command line are merged, and exit
+    /// available options: toml (default), json
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false, num_args = 0..=1, default_missing_value = "toml")]
+    pub config_dump: Option<String>,
+
+    /// Print the [machine] section of the current host, read from /sys, and exit
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub detect_machine: bool,
+
+    /// Print the cpu count, first and last timestamps and duration of this trace as json and exit
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub info: Option<String>,
+
+    /// Exit with code 2 when the unsupported and malformed lines of a trace exceed this many,
+    /// or this fraction of the lines read when below 1
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub fail_on_skip: Option<f64>,
+
+    /// Check the config and the header of every file without plotting
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub dry_run: bool,
+
+    /// Only plot the event count over time of every file, to choose min and max
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub preview: bool,
+
+    /// Only print a report of the migrations, switches and busiest tasks of every file
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub stats_only: bool,
+
+    /// Plot the files and print the time taken by every stage: trace-cmd report, header parse, event parse,
+    /// color table, plot build and plot write
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub benchmark: bool,
+
+    /// Plot the files again every time they are written to, e.g. while a trace is captured
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false)]
+    pub watch: bool,
+
+    /// Plot the second trace colored by where its tasks ran in the first one, the baseline, over the same time
+    #[serde(skip_deserializing)]
+    #[arg(long, required = false, num_args = 2, value_names = ["BASELINE", "CANDIDATE"])]
+    pub diff: Vec<String>,
+
+    /// Length in seconds of the windows of the preview histogram
+    #[default(0.01)]
+    #[arg(long, required = false)]
+    pub preview_bucket: f64,
+
+    /// Marker symbol by event name, e.g. wakeup = "circle", set in the [graph.event_symbols] table of the config
+    #[arg(skip)]
+    pub event_symbols: HashMap<String, String>,
+
+    /// Css color by event name, e.g. wakeup = "navy", set in the [graph.event_colors] table of the config
+    #[arg(skip)]
+    pub event_colors: HashMap<String, String>,
+
+    /// Class of task state strings, e.g. "K" = "uninterruptible", set in the [graph.task_states] table of the config
+    #[arg(skip)]
+    pub task_states: HashMap<String, String>,
+
     /// Options for static plot other than html
     #[clap_serde]
     #[command(flatten)]
@@ -97,6 +541,13 @@ pub struct Graph {
     pub files: Vec<String>
 }
 
+// annotation from the command line, written as time=label
+fn parse_annotation(arg: &str) -> Result<(f64, String), String> {
+    let (time, label) = arg.split_once('=').ok_or(format!("expected time=label, got {}", arg))?;
+    let time = time.parse().map_err(|_| format!("invalid annotation time {}", time))?;
+    Ok((time, label.to_string()))
+}
+
 #[derive(ClapSerde, Serialize, Deserialize)]
 #[derive(Debug, Clone)]
 pub struct Static {
@@ -115,10 +566,24 @@ pub struct Static {
     pub static_res_height: usize,
 
     /// Filetype of the static plot, available options: png, svg, webp, pdf, jpeg, eps
-    #[arg(long, default_value = "png", required = false)]
+    #[default(String::from("png"))]
+    #[arg(long, required = false)]
     pub filetype: String,
+
+    /// Scale factor of the static plot, multiplying its resolution for print quality
+    #[default(1.0)]
+    #[arg(long, required = false)]
+    pub static_scale: f64,
+
+    /// Width in pixels that the shorter switch segments of a static plot are widened to, 0 to draw their real duration
+    #[default(1.0)]
+    #[arg(long, required = false)]
+    pub min_segment_width: f64,
 }
 
+// filetypes the static plot can be written in
+const FILETYPES: [&str; 6] = ["png", "jpeg", "webp", "svg", "pdf", "eps"];
+
 #[derive(ClapSerde, Serialize, Deserialize)]
 #[derive(Debug, Clone)]
 #[command()]
@@ -138,6 +603,18 @@ pub struct Events {
     /// Migration events: unblock placement, load balancing, numa balancing
     #[arg(long, required = false)]
     pub show_migrate: bool,
+
+    /// Interrupt events: irq and softirq handler entries
+    #[arg(long, required = false)]
+    pub show_irq: bool,
+
+    /// Idle bands of the cpu_idle events, shaded by c-state depth
+    #[arg(long, required = false)]
+    pub show_cpu_idle: bool,
+
+    /// Events left out even with show_events, by legend name, e.g. numa swap, or migrate for every migration
+    #[arg(long, required = false)]
+    pub exclude_events: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -150,20 +627,258 @@ pub struct Config {
 
 // Priority order for config options:
 // Command line arguments > config file options > defaults (if present)
+// The config is read from the --config path, which must exist
+// Without it, ./tracing-tool-config.json is used if present, else ./tracing-tool-config.toml which is generated if missing
 pub fn config() -> Config {
-    let mut temp_str = read_to_string("./tracing-tool-config.toml");
-    if temp_str.is_err() {
-        let mut writer = File::create("./tracing-tool-config.toml").expect("Failed to generate config");
-        writer.write_all(default_config().as_bytes()).expect("Error while writing config");
-        temp_str = read_to_string("./tracing-tool-config.toml");
+    let (path, config_str) = match config_path_arg() {
+        Some(path) => {
+            let config_str = read_to_string(&path).unwrap_or_else(|err| {
+                eprintln!("Error: could not read config {}: {}", path, err);
+                exit(1);
+            });
+            (path, config_str)
+        }
+        None => {
+            let path = if Path::new("./tracing-tool-config.json").is_file() {
+                "./tracing-tool-config.json"
+            } else {
+                "./tracing-tool-config.toml"
+            };
+            let mut temp_str = read_to_string(path);
+            if temp_str.is_err() {
+                let mut writer = File::create(path).expect("Failed to generate config");
+                writer.write_all(default_config().as_bytes()).expect("Error while writing config");
+                temp_str = read_to_string(path);
+            }
+            (path.to_string(), temp_str.unwrap())
+        }
+    };
+    let table = parse_config(&path, &config_str);
+    let Config {machine, ..}: Config = Value::Table(table.clone()).try_into().expect("Failed to parse config");
+    let mut graph = read_graph(&table, None).merge_clap();
+
+    // the profile is only known once the command line is read, so the graph options are read again
+    if let Some(name) = graph.profile.clone() {
+        graph = read_graph(&table, Some(&name)).merge_clap();
+    }
+
+    if let Some(format) = &graph.print_default_config {
+        match format.as_str() {
+            "toml" => print!("{}", default_config()),
+            "json" => println!("{}", default_config_json()),
+            _ => { panic!("Invalid config format, available options: toml, json"); }
+        }
+        exit(0);
+    }
+
+    if graph.detect_machine {
+        print_detected_machine();
+        exit(0);
+    }
+
+    // a dry run reports the problems itself, and the info of a trace does not depend on the machine
+    let problems = machine.problems();
+    if !problems.is_empty() && !graph.dry_run && graph.info.is_none() {
+        for problem in problems {
+            eprintln!("Error: invalid machine config: {}", problem);
+        }
+        exit(1);
+    }
+    // watching only makes sense for the modes that write a plot of every file
+    let single_shot = [("stats_only", graph.stats_only), ("dry_run", graph.dry_run), ("info", graph.info.is_some()), ("combine", graph.combine), ("merge_files", graph.merge_files), ("diff", !graph.diff.is_empty())];
+    if let (true, Some((mode, _))) = (graph.watch, single_shot.iter().find(|(_, set)| *set)) {
+        eprintln!("Error: watch cannot be used with {}", mode);
+        exit(1);
+    }
+    // the stages are those of the data graph of every file
+    let other_modes = [("preview", graph.preview), ("split_sessions", graph.split_sessions), ("watch", graph.watch)];
+    if let (true, Some((mode, _))) = (graph.benchmark, single_shot.iter().chain(&other_modes).find(|(_, set)| *set)) {
+        eprintln!("Error: benchmark cannot be used with {}", mode);
+        exit(1);
+    }
+    if graph.fail_on_skip.is_some_and(|threshold| threshold < 0.0) {
+        eprintln!("Error: fail_on_skip cannot be negative, got {}", graph.fail_on_skip.unwrap());
+        exit(1);
+    }
+    if graph.color_by == "cgroup" {
+        match &graph.cgroup_regex {
+            None => {
+                eprintln!("Error: color_by cgroup needs a cgroup_regex to extract the group from the commands");
+                exit(1);
+            }
+            Some(regex) => if let Err(err) = regex::Regex::new(regex) {
+                eprintln!("Error: invalid cgroup_regex {}: {}", regex, err);
+                exit(1);
+            }
+        }
+    }
+    if graph.combine && graph.merge_files {
+        eprintln!("Error: combine and merge_files cannot be used together, the files are either subplots or one timeline");
+        exit(1);
+    }
+    if let Some(overrides) = &graph.plotly_layout_overrides {
+        match serde_json::from_str::<serde_json::Value>(overrides) {
+            Ok(serde_json::Value::Object(_)) => {}
+            Ok(_) => {
+                eprintln!("Error: plotly_layout_overrides must be a json object, got {}", overrides);
+                exit(1);
+            }
+            Err(err) => {
+                eprintln!("Error: plotly_layout_overrides is not valid json: {}", err);
+                exit(1);
+            }
+        }
     }
-    let config_str = temp_str.unwrap();
-    let Config {machine, graph}: Config = toml::from_str(&config_str).expect("Failed to parse config");
-    let graph = graph.merge_clap();
+    if let Some(cpu) = repeated_cpu(&graph.cpu_order) {
+        eprintln!("Error: cpu {} is listed more than once in cpu_order", cpu);
+        exit(1);
+    }
+    // checked before parsing, so a static plot does not fail after the whole render
+    let static_options = &graph.static_options;
+    if static_options.gen_static && !FILETYPES.contains(&static_options.filetype.as_str()) {
+        eprintln!("Error: invalid filetype {}, available options: {}", static_options.filetype, FILETYPES.join(", "));
+        exit(1);
+    }
+    if static_options.gen_static && static_options.static_scale <= 0.0 {
+        eprintln!("Error: static_scale must be positive, got {}", static_options.static_scale);
+        exit(1);
+    }
+    // file names are appended to the output path, which may be given without its trailing slash
+    if !graph.output_path.is_empty() && !graph.output_path.ends_with('/') {
+        graph.output_path.push('/');
+    }
+    if !graph.output_path.is_empty() && !graph.dry_run {
+        if let Err(err) = create_dir_all(&graph.output_path) {
+            eprintln!("Error: could not create output_path {}: {}", graph.output_path, err);
+            exit(1);
+        }
+    }
+
     let config = Config { machine, graph };
+    if let Some(format) = &config.graph.config_dump {
+        match format.as_str() {
+            "toml" => print!("{}", toml::to_string(&config).expect("Failed to write the config")),
+            "json" => println!("{}", serde_json::to_string_pretty(&config).expect("Failed to write the config")),
+            _ => { panic!("Invalid config format, available options: toml, json"); }
+        }
+        exit(0);
+    }
     config
 }
 
+// first cpu found twice in the list
+fn repeated_cpu(cpus: &[u32]) -> Option<u32> {
+    cpus.iter().enumerate().find(|(index, cpu)| cpus[..*index].contains(cpu)).map(|(_, cpu)| *cpu)
+}
+
+// the config path has to be known before the command line is merged with the config, so it is looked up directly
+fn config_path_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+// a json config has the same structure as the toml one
+fn parse_config(path: &str, config_str: &str) -> Table {
+    if path.ends_with(".json") {
+        serde_json::from_str(config_str).expect("Failed to parse config")
+    } else {
+        toml::from_str(config_str).expect("Failed to parse config")
+    }
+}
+
+// [machine] section of the current host, to paste in the config
+// The problems are printed as comments, e.g. when numa nodes are not the sockets of the host
+fn print_detected_machine() {
+    #[derive(Serialize)]
+    struct MachineSection {
+        machine: Machine,
+    }
+
+    let machine = Machine::detect().unwrap_or_else(|err| {
+        eprintln!("Error: cannot detect the machine: {}", err);
+        exit(1);
+    });
+    for problem in machine.problems() {
+        println!("# Warning: {}", problem);
+    }
+    print!("{}", toml::to_string(&MachineSection { machine }).expect("Failed to write the machine config"));
+}
+
+// the [graph] options, with those of [profiles.<name>] and then the environment merged over them
+fn read_graph(table: &Table, profile: Option<&str>) -> Graph {
+    let mut table = table.clone();
+    let mut graph = table.remove("graph").and_then(|graph| graph.as_table().cloned()).unwrap_or_default();
+
+    if let Some(name) = profile {
+        let profiles = table.remove("profiles").and_then(|profiles| profiles.as_table().cloned()).unwrap_or_default();
+        let Some(Value::Table(profile)) = profiles.get(name) else {
+            let names: Vec<&String> = profiles.keys().collect();
+            eprintln!("Error: no profile named {} in the config, available profiles: {:?}", name, names);
+            exit(1);
+        };
+        merge_table(&mut graph, profile);
+    }
+
+    apply_env(&mut graph);
+    Value::Table(graph).try_into().expect("Failed to parse config")
+}
+
+// Environment variables override the option of the same name in upper case, prefixed with STT_
+// e.g. STT_OUTPUT_PATH, STT_COLOR_BY, STT_WEBGL, or STT_SHOW_MIGRATE for [graph.events]
+// Values are read as toml, falling back to a string, so STT_CPU_FILTER="[0, 1]" works too
+fn apply_env(graph: &mut Table) {
+    for (key, raw) in std::env::vars_os().filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?))) {
+        let Some(option) = key.strip_prefix("STT_").map(|option| option.to_lowercase()) else {
+            continue;
+        };
+
+        // options of the nested tables are set in place, the others at the top of [graph]
+        let target = match graph.iter().find_map(|(name, value)| match value {
+            Value::Table(nested) if nested.contains_key(&option) => Some(name.clone()),
+            _ => None,
+        }) {
+            Some(nested) => graph[&nested].as_table_mut().unwrap(),
+            None => &mut *graph,
+        };
+
+        let value = toml::from_str::<Table>(&format!("value = {}", raw))
+                        .ok()
+                        .and_then(|mut parsed| parsed.remove("value"))
+                        .unwrap_or(Value::String(raw.clone()));
+        // match the type of the value it replaces
+        let value = match (target.get(&option), value) {
+            (Some(Value::String(_)), _) => Value::String(raw),
+            (Some(Value::Float(_)), Value::Integer(number)) => Value::Float(number as f64),
+            (_, value) => value,
+        };
+        target.insert(option, value);
+    }
+}
+
+// overwrite the keys of base with those of other, nested tables are merged key by key
+fn merge_table(base: &mut Table, other: &Table) {
+    for (key, value) in other {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base_table)), Value::Table(other_table)) => merge_table(base_table, other_table),
+            _ => { base.insert(key.clone(), value.clone()); }
+        }
+    }
+}
+
+// the default config as json, without the comments
+pub fn default_config_json() -> String {
+    let table: Table = toml::from_str(&default_config()).expect("Failed to parse default config");
+    serde_json::to_string_pretty(&table).expect("Failed to convert default config")
+}
+
 pub fn default_config() -> String {
     String::from("[machine]
     cpus = 64
@@ -181,36 +896,181 @@ pub fn default_config() -> String {
                                 [48, 63]
                             ] 
                         ]
+    # hops between numa nodes, row i column j from node i to node j, the nodes are in a line if missing
+    # node_hops = [[0, 1], [1, 0]]
 
 
 [graph]
-    # color options: pid, command, parent
+    # color options: pid, command, parent, prio, cgroup
+    # prio draws real-time tasks from dark red to yellow and normal ones from dark to light blue, by priority
+    # cgroup colors the tasks by the group cgroup_regex extracts from their command
     color_by = \"parent\"
 
+    # for color_by cgroup, as the trace has no cgroups: the first capture group of the regex, or its whole match,
+    # is the group of a command, e.g. the container prefix of the commands, and the other commands are ungrouped
+    # cgroup_regex = \"^(ctr-[0-9a-f]+)-\"
+
+    # most distinct colors with color_by pid, command or cgroup, the ones in the most switches keep theirs
+    # the others are drawn in gray, thousands of random colors cannot be told apart anyway
+    # max_colors = 50
+
+    # view options: cpu, socket
+    # socket draws one row per numa node, showing when any of its cpus is busy
+    view = \"cpu\"
+
+    # migrate color options: category, distance
+    # distance colors migrations from green to red by the number of numa node hops
+    migrate_color = \"category\"
+
+    # legend order options: fixed, frequency_desc, name
+    # fixed keeps the events grouped by kind, frequency_desc puts the most frequent events first
+    legend_sort = \"fixed\"
+
+    # only draw the migrations between numa nodes at least this many hops apart, with node_hops of [machine]
+    # 1 leaves out the migrations within a node, 0 draws them all
+    min_migration_distance = 0
+
     # if true cpus are arranged as per sockets
     socket_order = false
 
+    # grid lines on both axes, with a cpu tick every cpu_tick_step rows
+    # with socket_order the ticks restart at the first cpu of each socket, 0 leaves the ticks to plotly
+    show_grid = false
+    cpu_tick_step = 0
+
+    # only plot the listed cpus, e.g. [0, 1, 2], all cpus are plotted if empty
+    cpu_filter = []
+
+    # rows of the cpus in this order, e.g. [4, 5, 0, 1], the cpus not listed follow in natural order
+    # overrides socket_order, the natural or socket order is used if empty
+    cpu_order = []
+
+    # lines between the numa nodes of numa_node_ranges, labelled at the first row of each node
+    # the cpus of a node need to be on consecutive rows, which socket_order makes sure of
+    node_separators = false
+
+    # drop the rows of the cpus that only ran idle, the rows keep their real cpu number
+    hide_idle_cpus = false
+
+    # empty rows of space below the first and above the last cpu row, the axis otherwise ends at the drawn rows
+    pad_y = 0
+
+    # detailed lane of a single cpu, with the command names written on the segments wide enough
+    # single_cpu = 3
+
     # start plot after the first sleep command
     sleep = false
 
+    # command and event used as the start marker when sleep = true
+    # event options: exit, fork, exec
+    start_marker_command = \"sleep\"
+    start_marker_event = \"exit\"
+
     # whether to have filename as title on top of graph
     show_title = true
 
+    # error on out of order timestamps in the trace, otherwise they are counted and reported
+    strict_order = false
+
+    # end the plot at the exit of this command, the whole trace is plotted if not set
+    # end_marker_command = \"teardown\"
+
+    # companion plot with one bar per task from fork to exit, colored by parent
+    lifetime_view = false
+
+    # companion bar chart of the total on-cpu time of every command, largest first
+    occupancy_chart = false
+
+    # footer with the path, size and sha-256 of the trace, hovering it shows the options of the plot
+    embed_provenance = false
+
+    # stack all files in a single plot named combined, one subplot per file, for comparison
+    combine = false
+
+    # plot all files as one trace named merged, for traces recorded separately on groups of cpus of the same machine
+    # the events are interleaved by timestamp, and a cpu with events in more than one file is an error
+    merge_files = false
+
+    # a file of concatenated reports, each starting with its cpus= line, gets one subplot per report
+    split_sessions = false
+
+    # overlay the runqueue depth, the runnable task count of the displayed cpus, on a secondary y axis
+    runqueue_depth = false
+
+    # overlay a faint line per cpu of its context switches per second, counted over windows of switch_rate_window seconds
+    # high rates point at lock contention or tiny timeslices
+    show_switch_rate = false
+    switch_rate_window = 0.01
+
+    # thin heat strip above the cpu rows with the event count of every activity_bucket seconds, to see the busy
+    # and quiet periods of the whole trace while zoomed in
+    activity_strip = false
+    activity_bucket = 0.01
+
+    # busy percentage of every cpu at the right of its row, the time it runs tasks other than idle over the plotted
+    # range, which is min to max with custom_range, averaged over the cpus of the row in the socket view
+    show_utilization = false
+
     # whether to create a html plot
     create_html = true
 
     # set the html plot's interactivity
     interactive = true
 
+    # write the plotly.js library into every html file, which then opens anywhere without network access, at the
+    # cost of about 3.5MB per file, set to false for small files that load plotly.js from its cdn when opened
+    embed_plotlyjs = true
+
+    # decimal places of the timestamps in the hover text and of the duration, 6 shows microseconds
+    timestamp_precision = 6
+
+    # opacity and width of the switch lines, lower the opacity to see the density of busy cpus
+    line_opacity = 1.0
+    line_width = 1.0
+
+    # width of the migration lines, thicker than line_width makes them stand out on dense plots
+    migrate_line_width = 1.0
+
+    # arrows from the waker cpu to the wakee cpu for waking and wakeup events, with migrate_line_width
+    # off by default, they crowd the plot on busy traces
+    wakeup_arrows = false
+
     # transparent marker count for hover info between switch events
     line_marker_count = 0
+
+    # group the segments of a pid across all cpus, to follow a task as it migrates
+    highlight_pid_on_hover = false
+
+    # threads take the color of their process, and highlight with it, with color_by pid or parent
+    # the trace has no tgid: a forked task is in the group of its parent, until it execs and leads its own group
+    group_by_tgid = false
     
     # Switch events smaller than limit will be ignored if not interactive
     limit = 0.0
 
+    # most switch segments to draw, to keep the html of very large traces usable
+    # above it the shortest segments of each cpu are dropped, every cpu keeping its share
+    # max_segments = 200000
+
+    # a switch segment separated from the previous one of its task on the same cpu by less than this many seconds
+    # is drawn in the same plotly trace, nothing is dropped but the html has far fewer traces to open
+    merge_adjacent_below = 0.0
+
     # webgl improves performance especially for large graphs, but may cause pixelation
     webgl = false
 
+    # x axis in trace timestamps instead of seconds since the start of the plot
+    # min and max below are then timestamps as well
+    absolute_time = false
+
+    # hover time options: absolute, relative
+    # timestamps in the hover text, set apart from the x axis: absolute keeps the kernel timestamps to search the
+    # trace with, relative shows the seconds since the start of the plot
+    hover_time = \"absolute\"
+
+    # labeled vertical lines, at times in the same space as the x axis, e.g. [[0.5, \"GC pause\"]]
+    annotations = []
+
     # whether to show only a part of the graph
     custom_range = false
 
@@ -218,15 +1078,40 @@ pub fn default_config() -> String {
     min = 0.0
     max = 0.0
 
+    # skip to min with a binary search of the trace file, much faster on large traces, pipes are read from the start
+    # the events before the seek are not seen: cpus without a switch in between start at their first switch in the
+    # range, and migrations of tasks woken before it are not drawn, set to false for exact range boundaries
+    seek_to_min = true
+
+    # window length in seconds of the --preview histogram, which shows where the activity is to pick min and max
+    preview_bucket = 0.01
+
+    # wall-clock time of the first event of the trace, in RFC3339, to show dates on the x axis
+    # kernel timestamps are seconds since boot, this lines the plot up with application logs
+    # trace_start_wallclock = \"2024-05-01T14:30:00.250+02:00\"
+
+    # open the plot zoomed on the first time this command runs, zoom out to see the rest
+    # focus_command = \"postgres\"
+
     # whether to show the generated html file after creation
     show_html = true
 
+    # never open a browser, e.g. on CI, the html and static files are still written
+    headless = false
+
     # browser program name, if empty default is used
     browser = \"\"
+
+    # json object merged into the plotly layout of the html plots, key by key, for styling without an option here
+    # see https://plotly.com/javascript/reference/layout/, static images are not affected
+    # plotly_layout_overrides = '{\"font\": {\"family\": \"Courier New\"}, \"legend\": {\"orientation\": \"h\"}}'
     
-    # Location for the generated file(s)
+    # Location for the generated file(s), created if missing
     output_path = \"\"
 
+    # do not print the parse statistics summary after each file
+    quiet = false
+
     # input files, can be given as an array here or via commmand line arguments
     files = [\"\"]
 
@@ -243,6 +1128,35 @@ pub fn default_config() -> String {
     # Migration events: unblock placement, load balancing, numa balancing
     show_migrate = false
 
+    # Interrupt events: irq and softirq handler entries, as ticks at the bottom of the cpu rows
+    show_irq = false
+
+    # Idle periods from the cpu_idle events, as bands shaded by c-state depth on the cpu rows
+    show_cpu_idle = false
+
+    # events left out even when show_events is true, by their legend name, e.g. [\"waking\", \"numa swap\"]
+    # \"migrate\" leaves out every migration, \"cpu idle\" the idle bands, they are still counted in the parse stats
+    exclude_events = []
+
+[graph.event_symbols]
+    # plotly marker symbol of an event, to tell the events apart by shape as well as color
+    # names with spaces are quoted, e.g. \"wakeup new\" = \"square\"
+    # wakeup = \"circle\"
+    # waking = \"triangle-up\"
+
+[graph.event_colors]
+    # css color of an event, a name, #rrggbb or rgb(r, g, b), for the markers, lines and legend of the event
+    # names are the legend names with spaces, e.g. \"off-socket load balancing\" = \"#e66100\"
+    # wakeup = \"navy\"
+    # irq = \"rgb(90, 90, 90)\"
+
+[graph.task_states]
+    # class of the state strings of sched_switch, which are otherwise classified by their first letter: R running,
+    # S or P sleeping, D uninterruptible, T or t stopped, X or Z dead, I idle, like D|K is uninterruptible
+    # the classes decide the enter D-state markers and the runqueue depth, for kernels that write states differently
+    # classes: running, sleeping, uninterruptible, stopped, dead, idle, unknown
+    # \"K\" = \"uninterruptible\"
+
 [graph.static_options]
     # generate static graph in a different file format
     gen_static = false
@@ -250,8 +1164,22 @@ pub fn default_config() -> String {
     static_res_width = 1920
     static_res_height = 1080
 
+    # multiplies the resolution, e.g. 3.0 for print quality
+    static_scale = 1.0
+
     # filetype options = png, jpeg, webp, svg, pdf, eps
     filetype = \"png\"
+
+    # segments narrower than this many pixels of static_res_width are widened to it in the static plot only,
+    # so the short runs of a busy cpu are not lost to sub-pixel lines, 0.0 draws their real duration
+    min_segment_width = 1.0
+
+# profiles override some of the [graph] options, selected with --profile <name>
+# command line arguments still take precedence over the profile
+# [profiles.migration]
+#     socket_order = true
+#     [profiles.migration.events]
+#         show_migrate = true
 "
 )
 }
\ No newline at end of file
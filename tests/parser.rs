@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use tracing_tool::graph::parser::*;
+
+// every action of a trace in tests/fixtures
+fn actions(fixture: &str) -> Vec<Action> {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+    let mut reader = TraceParser::new(&path);
+    let mut actions = Vec::new();
+    while let Some((action, ..)) = reader.next_action() {
+        actions.push(action);
+    }
+    actions
+}
+
+fn action(process: &str, pid: u32, cpu: u32, timestamp: f64, event: Events) -> Action {
+    Action { process: process.to_string(), pid, cpu, timestamp, event }
+}
+
+#[test]
+fn wakeup_events() {
+    assert_eq!(actions("wakeup.txt"), vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedWaking { command: "sleep".to_string(), pid: 1001, target_cpu: 2 }),
+        action("bash", 1000, 0, 100.000110, Events::SchedWakeIdleNoIpi { cpu: 2 }),
+        // the cpu of the waker is the one the task was woken from
+        action("<idle>", 0, 2, 100.000120, Events::SchedWakeup { command: "sleep".to_string(), pid: 1001, prev_cpu: Some(0), cpu: 2 }),
+        action("bash", 1000, 0, 100.000200, Events::SchedWakeup { command: "kworker/0:1".to_string(), pid: 50, prev_cpu: None, cpu: 0 }),
+        // forked before the trace started
+        action("bash", 1000, 0, 100.000300, Events::SchedWakeupNew { command: "bash".to_string(), pid: 1002, parent_cpu: 1, cpu: 1, forked: false }),
+    ]);
+}
+
+#[test]
+fn wakeup_formats_of_old_and_new_kernels() {
+    let expected = vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedWaking { command: "sleep".to_string(), pid: 1001, target_cpu: 2 }),
+        action("<idle>", 0, 2, 100.000120, Events::SchedWakeup { command: "sleep".to_string(), pid: 1001, prev_cpu: Some(0), cpu: 2 }),
+        action("bash", 1000, 0, 100.000200, Events::SchedWakeup { command: "Web Content".to_string(), pid: 1500, prev_cpu: None, cpu: 3 }),
+        action("bash", 1000, 0, 100.000300, Events::SchedWakeupNew { command: "bash".to_string(), pid: 1002, parent_cpu: 1, cpu: 1, forked: false }),
+    ];
+    // comm= pid= prio= success= target_cpu= fields
+    assert_eq!(actions("wakeup-4.x.txt"), expected);
+    // command:pid [prio] CPU:cpu
+    assert_eq!(actions("wakeup-6.x.txt"), expected);
+}
+
+#[test]
+fn process_events() {
+    assert_eq!(actions("process.txt"), vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedProcessFork { command: "bash".to_string(), pid: 1000, child_command: "bash".to_string(), child_pid: 1001 }),
+        action("bash", 1000, 0, 100.000150, Events::SchedWakeupNew { command: "bash".to_string(), pid: 1001, parent_cpu: 0, cpu: 1, forked: true }),
+        action("bash", 1001, 1, 100.000200, Events::SchedProcessExec { filename: "/opt/my app/run".to_string(), pid: 1001, old_pid: 1001 }),
+        action("run", 1001, 1, 100.000300, Events::SchedProcessExit { command: "run".to_string(), pid: 1001 }),
+        action("bash", 1000, 0, 100.000400, Events::SchedProcessWait { command: "bash".to_string(), pid: 0 }),
+        action("rcu_sched", 11, 0, 100.000500, Events::SchedProcessFree { command: "run".to_string(), pid: 1001 }),
+    ]);
+}
+
+#[test]
+fn switch_events_with_tricky_commands() {
+    let switch = |old_command: &str, old_pid, old_prio, state: &str, new_command: &str, new_pid| Events::SchedSwitch {
+        old_command: old_command.to_string(), old_pid, old_prio: Some(old_prio), state: state.to_string(),
+        new_command: new_command.to_string(), new_pid, new_prio: Some(120),
+    };
+    let expected = vec![
+        action("<idle>", 0, 0, 100.000100, switch("swapper/0", 0, 120, "R", "kworker/0:1-events", 77)),
+        // latency column after the cpu
+        action("kworker/0:1-events", 77, 0, 100.000200, switch("kworker/0:1-events", 77, 120, "I", "Web Content", 1500)),
+        // no colons after the timestamp and the event name
+        action("Web Content", 1500, 0, 100.000300, switch("Web Content", 1500, 120, "D", "my-app-2", 1600)),
+        // short cpu field, real-time priority
+        action("my-app-2", 1600, 0, 100.000400, switch("my-app-2", 1600, 49, "R+", "swapper/0", 0)),
+    ];
+    // command:pid [prio] state ==> command:pid [prio]
+    assert_eq!(actions("switch.txt"), expected);
+    // prev_comm= prev_pid= prev_prio= prev_state= ==> next_comm= next_pid= next_prio=
+    assert_eq!(actions("switch-named.txt"), expected);
+}
+
+#[test]
+fn switch_without_prio_fields() {
+    let switch = |old_command: &str, old_pid, state: &str, new_command: &str, new_pid| Events::SchedSwitch {
+        old_command: old_command.to_string(), old_pid, old_prio: None, state: state.to_string(),
+        new_command: new_command.to_string(), new_pid, new_prio: None,
+    };
+    let expected = vec![
+        action("<idle>", 0, 1, 100.000100, switch("swapper/1", 0, "R", "sleep", 1001)),
+        action("sleep", 1001, 1, 100.000200, switch("sleep", 1001, "S", "Web Content", 1500)),
+    ];
+    // prev_comm= prev_pid= prev_state= ==> next_comm= next_pid=
+    assert_eq!(actions("switch-no-prio.txt"), expected);
+    // command:pid state ==> command:pid
+    assert_eq!(actions("switch-colon-no-prio.txt"), expected);
+}
+
+#[test]
+fn migration_and_numa_events() {
+    let migrate = |command: &str, pid, orig_cpu, dest_cpu, state| Events::SchedMigrateTask {
+        command: command.to_string(), pid, orig_cpu, dest_cpu, state,
+    };
+    assert_eq!(actions("migrate.txt"), vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedWaking { command: "sleep".to_string(), pid: 1001, target_cpu: 4 }),
+        // unblock placement
+        action("bash", 1000, 0, 100.000110, migrate("sleep", 1001, 1, 4, Wstate::Waking(0, 4, 100.000100))),
+        action("<idle>", 0, 4, 100.000120, Events::SchedWakeup { command: "sleep".to_string(), pid: 1001, prev_cpu: Some(0), cpu: 4 }),
+        // load balancing
+        action("<idle>", 0, 4, 100.000130, migrate("sleep", 1001, 4, 5, Wstate::Woken)),
+        action("bash", 1000, 0, 100.000200, Events::SchedSwapNuma { src_pid: 1000, src_cpu: 0, dst_pid: 1001, dst_cpu: 5 }),
+        // numa balancing, the migration of the swap
+        action("bash", 1000, 0, 100.000210, migrate("bash", 1000, 0, 5, Wstate::Numa(0, 5))),
+        action("bash", 1000, 0, 100.000300, Events::SchedStickNuma { src_pid: 1000, src_cpu: 0, dst_pid: 0, dst_cpu: -1 }),
+        action("bash", 1000, 0, 100.000400, Events::SchedMoveNuma { src_pid: 1000, src_cpu: 0, dst_cpu: 6 }),
+    ]);
+}
+
+#[test]
+fn interrupt_and_unsupported_events() {
+    assert_eq!(actions("irq.txt"), vec![
+        action("<idle>", 0, 1, 100.000100, Events::IrqHandlerEntry { irq: 24, name: "eth0".to_string(), cpu: 1 }),
+        action("bash", 1000, 0, 100.000200, Events::SoftirqEntry { vec: 3, action: "NET_RX".to_string(), cpu: 0 }),
+        action("bash", 1000, 0, 100.000300, Events::IrqHandlerEntry { irq: 9, name: "acpi timer".to_string(), cpu: 0 }),
+        action("bash", 1000, 0, 100.000400, Events::NotSupported("sched_stat_runtime".to_string())),
+    ]);
+}
+
+#[test]
+fn header_variants() {
+    let switch = |cpu| vec![action("bash", 1000, cpu, 100.000100, Events::SchedSwitch {
+        old_command: "bash".to_string(), old_pid: 1000, old_prio: Some(120), state: "R".to_string(),
+        new_command: "sleep".to_string(), new_pid: 1001, new_prio: Some(120),
+    })];
+    for (fixture, cpu_count, cpu) in [("header-online.txt", 4, 0), ("header-version.txt", 16, 5)] {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+        assert_eq!(TraceParser::new(&path).cpu_count, cpu_count, "{}", fixture);
+        // the lines before the header are not events
+        assert_eq!(actions(fixture), switch(cpu), "{}", fixture);
+    }
+}
+
+#[test]
+fn line_without_a_file() {
+    let mut process_state = HashMap::new();
+    assert_eq!(parse_line("", &mut process_state), None);
+    assert_eq!(parse_line("CPU:1", &mut process_state), None);
+
+    let fork = parse_line("bash-1000  [000]  100.000100: sched_process_fork: comm=bash pid=1000 child_comm=bash child_pid=1001", &mut process_state);
+    assert!(fork.is_some());
+    // the waking state is carried to the next line
+    let wakeup = parse_line("bash-1000  [000]  100.000150: sched_wakeup_new: bash:1001 [120] CPU:001", &mut process_state).unwrap();
+    assert_eq!(wakeup.event, Events::SchedWakeupNew { command: "bash".to_string(), pid: 1001, parent_cpu: 0, cpu: 1, forked: true });
+}
+
+#[test]
+fn timestamps_only_scan() {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), "migrate.txt");
+    let mut reader = TraceParser::new(&path);
+    reader.scan_timestamps();
+    assert_eq!(reader.cpu_count, 8);
+    assert_eq!(reader.first_timestamp, Some(100.000100));
+    assert_eq!(reader.last_timestamp, Some(100.000400));
+}
+
+#[test]
+fn truncated_last_line() {
+    assert_eq!(actions("truncated.txt"), vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedWaking { command: "sleep".to_string(), pid: 1001, target_cpu: 1 }),
+        action("<idle>", 0, 1, 100.000200, Events::SchedSwitch {
+            old_command: "swapper/1".to_string(), old_pid: 0, old_prio: Some(120), state: "R".to_string(),
+            new_command: "sleep".to_string(), new_pid: 1001, new_prio: Some(120),
+        }),
+    ]);
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), "truncated.txt");
+    let mut reader = TraceParser::new(&path);
+    while reader.next_action().is_some() {}
+    // the half written switch is skipped, the trace ends with the last complete event
+    assert_eq!(reader.malformed, 1);
+    let (offset, line) = &reader.parse_errors[0];
+    assert!(line.ends_with("S ==> swa"), "{}", line);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents[*offset as usize..].trim_start().starts_with(line.as_str()));
+    assert_eq!(reader.last_timestamp, Some(100.000200));
+}
+
+#[test]
+fn cpu_column_with_brackets_or_named() {
+    let expected = vec![
+        action("bash", 1000, 0, 100.000100, Events::SchedWaking { command: "sleep".to_string(), pid: 1001, target_cpu: 2 }),
+        // latency column after the cpu
+        action("kworker/0:1-events", 77, 2, 100.000120, Events::SchedSwitch {
+            old_command: "kworker/0:1-events".to_string(), old_pid: 77, old_prio: Some(120), state: "I".to_string(),
+            new_command: "sleep".to_string(), new_pid: 1001, new_prio: Some(120),
+        }),
+        action("sleep", 1001, 2, 100.000200, Events::SchedMigrateTask {
+            command: "sleep".to_string(), pid: 1001, orig_cpu: 2, dest_cpu: 3, state: Wstate::Waking(0, 2, 100.000100),
+        }),
+    ];
+    // [002] as written by trace-cmd
+    assert_eq!(actions("cpu-bracket.txt"), expected);
+    // cpu=2 of traces converted from perf
+    assert_eq!(actions("cpu-named.txt"), expected);
+}
+
+#[test]
+fn migrations_with_and_without_prio() {
+    let migrate = |command: &str, pid, orig_cpu, dest_cpu| Events::SchedMigrateTask {
+        command: command.to_string(), pid, orig_cpu, dest_cpu, state: Wstate::Woken,
+    };
+    let expected = vec![
+        action("bash", 1000, 0, 100.000110, migrate("sleep", 1001, 1, 4)),
+        action("<idle>", 0, 4, 100.000130, migrate("Web Content", 1500, 4, 5)),
+    ];
+    // comm= pid= prio= orig_cpu= dest_cpu=
+    assert_eq!(actions("migrate-prio.txt"), expected);
+    // the prio= field is not written by every kernel
+    assert_eq!(actions("migrate-no-prio.txt"), expected);
+}